@@ -0,0 +1,68 @@
+extern crate robbot;
+extern crate chrono;
+extern crate env_logger;
+#[macro_use]
+extern crate log;
+
+use robbot::migrate::*;
+
+use chrono::NaiveDate;
+
+use std::env;
+use std::path::Path;
+use std::process;
+
+use env_logger::LogBuilder;
+use log::LogLevelFilter;
+
+// rewrites a chat root's legacy "<user_id> <text>" logs as json lines. run it with the bot stopped
+fn main() {
+    let mut builder = LogBuilder::new();
+    builder.filter(None, LogLevelFilter::Info);
+    if let Ok(filters) = env::var("RUST_LOG") {
+        builder.parse(&filters);
+    }
+    builder.init().expect("a logger");
+
+    let args : Vec<String> = env::args().skip(1).collect();
+    let usage = "usage: migrate [--dry-run] [--escaped-since=<first day the bot escaped line breaks, e.g. 2017-01-02>] <chat root, e.g. ../chat>";
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    let escaped_since = match args.iter().find(|a| a.starts_with("--escaped-since=")) {
+        Some(arg) => match NaiveDate::parse_from_str(&arg["--escaped-since=".len()..], "%Y-%m-%d") {
+            Ok(date) => Some(date),
+            Err(_) => {
+                error!("{}", usage);
+                process::exit(2);
+            },
+        },
+        None => None,
+    };
+    let root = match args.iter().find(|a| !a.starts_with("--")) {
+        Some(root) => root.clone(),
+        None => {
+            error!("{}", usage);
+            process::exit(2);
+        },
+    };
+
+    match migrate_root(Path::new(&root), dry_run, escaped_since) {
+        Ok(report) => {
+            let lines : usize = report.migrated.iter().map(|&(_, ref r)| r.converted).sum();
+            let unreadable : usize = report.migrated.iter().map(|&(_, ref r)| r.unreadable).sum();
+            info!("{}{} files, {} lines converted, {} unreadable lines left as they were, {} files already migrated",
+                if dry_run { "would migrate " } else { "migrated " }, report.migrated.len(), lines, unreadable, report.skipped.len());
+            if !dry_run && !report.migrated.is_empty() {
+                info!("originals kept as *.log.{}", BACKUP_EXTENSION);
+            }
+            if !report.refused.is_empty() {
+                let lines : usize = report.refused.iter().map(|&(_, ref numbers)| numbers.len()).sum();
+                warn!("{} lines in {} files could have been escaped or not and were left alone, run again with --escaped-since to convert them",
+                    lines, report.refused.len());
+            }
+        },
+        Err(e) => {
+            error!("migration stopped -> {:?}", e);
+            process::exit(1);
+        },
+    }
+}
@@ -1,20 +1,27 @@
 
 use telegram_bot;
-use telegram_bot::{Api, ParseMode, ListeningMethod, ListeningAction, User, Message, MessageType, Chat};
+use telegram_bot::{Api, ParseMode, ListeningMethod, ListeningAction, Message, MessageType, Chat};
 
 use command::*;
 use model::*;
 use persistence::*;
 use history::*;
-use unseeded_rng;
+use {unseeded_rng, seeded_rng, Seed, HashMap, HashSet};
 use tokenizer::*;
 use generate::*;
 use search::*;
+use index::*;
+use html::*;
+use stats::*;
+use grep::*;
 use dice::*;
+use constraint::*;
+use poetry::*;
+use convo::*;
 
 use glob::glob;
 
-use rand::XorShiftRng;
+use rand::{Rng, XorShiftRng};
 
 use std::path::{PathBuf};
 
@@ -28,6 +35,131 @@ pub struct Bot {
     listener: telegram_bot::Listener,
     persistence: Persistence,
     rand: XorShiftRng,
+    generation: GenerationConfig,
+    search: SearchConfig,
+    index: SearchIndex,
+    groups: HashMap<u64, GroupState>,
+}
+
+// what the bot remembers about each group between messages
+#[derive(Default)]
+pub struct GroupState {
+    // the last seeded command, so /explain can say how to replay it
+    pub last_seeded: Option<(String, Seed)>,
+    // the last search, so /more can show the rest
+    pub last_search: Option<SearchPage>,
+}
+
+// ranked results of a search and how far through them the group has got
+pub struct SearchPage {
+    pub query: String,
+    pub terms: Vec<String>,
+    pub docs: Vec<DocId>,
+    pub shown: usize,
+}
+
+// the same query /search would run, but only over this group's messages
+fn group_matches(index:&SearchIndex, query_text:&str, user_id:UserId, group_id:u64, config:&SearchConfig) -> Result<(Query, Vec<DocId>), String> {
+    let mut query = try!(parse_query(query_text, user_id));
+    index.expand(&mut query, config);
+    let mut docs = index.search(&query);
+    docs.retain(|&doc| in_group(index, doc, group_id));
+    Ok((query, docs))
+}
+
+// the pattern, and the users picked out by any from:name in it (everyone if there aren't any).
+// a from: that isn't anyone is left in the pattern, unless the pattern comes after it
+pub fn parse_grep(text:&str, user_id:UserId) -> Result<(String, HashSet<UserId>), String> {
+    let mut users : HashSet<UserId> = HashSet::default();
+    let mut pattern : Vec<&str> = Vec::new();
+    let is_from = |word:&str| word.to_lowercase().starts_with("from:");
+    let words : Vec<&str> = text.split(' ').collect();
+    for (i, word) in words.iter().enumerate() {
+        if !is_from(word) {
+            pattern.push(word);
+            continue
+        }
+        match user_for_name(&word[5..].to_lowercase(), user_id) {
+            Ok(found) => {
+                users.insert(found);
+            },
+            Err(e) => {
+                if words[i + 1..].iter().any(|w| !w.is_empty() && !is_from(w)) {
+                    return Err(e)
+                }
+                pattern.push(word);
+            },
+        }
+    }
+    if users.is_empty() {
+        users = all_user_ids().into_iter().collect();
+    }
+
+    let pattern = String::from(pattern.join(" ").trim());
+    if pattern.is_empty() {
+        Err(String::from("Grep for what? e.g. /grep pi+zza from:robe"))
+    } else {
+        Ok((pattern, users))
+    }
+}
+
+// "name: line" with the name in bold
+fn said(user_name:&str, line:&str) -> Html {
+    let mut msg = Html::new();
+    msg.bold(&format!("{}:", user_name)).text(" ").text(line);
+    msg
+}
+
+fn describe_origin(result:&SearchResult, group_id:u64) -> String {
+    let date = result.date.map(|d| d.format("%Y-%m-%d").to_string()).unwrap_or_else(|| String::from("imported history"));
+    match result.group {
+        Some(group) if group == group_id => format!("{}, here", date),
+        Some(group) => format!("{}, group {}", date, group),
+        None => date,
+    }
+}
+
+// the next page of results, with the messages around each one
+fn next_search_page(index:&SearchIndex, page:&mut SearchPage, config:&SearchConfig, group_id:u64) -> Html {
+    let total = page.docs.len();
+    let end = (page.shown + config.page_size).min(total);
+
+    let mut message = Html::new();
+    if page.shown == 0 {
+        message.text("Searched for ").bold(&page.query).text(&format!(" found {} results", total));
+    } else {
+        message.text("More for ").bold(&page.query).text(&format!(", {} to {} of {}", page.shown + 1, end, total));
+    }
+    message.line().line();
+
+    for &doc in &page.docs[page.shown..end] {
+        let result = match index.result_with_context(doc, config.context) {
+            Ok(result) => result,
+            Err(e) => {
+                warn!("couldn't read search result -> {:?}", e);
+                continue
+            },
+        };
+        message.italic(&describe_origin(&result, group_id)).line();
+        for &(user_id, ref text) in &result.before {
+            message.text(&format!("    {}: {}", username_for_id(user_id), text)).line();
+        }
+        message.bold(&format!("{}:", display_name(result.user_id, result.username.as_ref().map(|u| u.as_str())))).text(" ").highlighted(&result.full_text, &page.terms);
+        if result.forwarded {
+            message.text(" ").italic("(forwarded)");
+        }
+        message.line();
+        for &(user_id, ref text) in &result.after {
+            message.text(&format!("    {}: {}", username_for_id(user_id), text)).line();
+        }
+        message.line();
+    }
+    page.shown = end;
+
+    if end < total {
+        message.text(&format!("/more for the next {}", config.page_size.min(total - end)));
+    }
+    message
 }
 
 impl Bot {
@@ -36,7 +168,7 @@ impl Bot {
         let history_in_path = PathBuf::from(history_path);
         let history_out_path = chat_path.join("history.log");
 
-        println!("chat {:?} history {:?} history_out {:?}", chat_path, history_path, history_out_path);
+        info!("chat {:?} history {:?} history_out {:?}", chat_path, history_path, history_out_path);
 
         let persistence = Persistence { root_path: chat_path.clone() };
         persistence.ensure_root().unwrap();
@@ -48,6 +180,7 @@ impl Bot {
 
         let paths = glob_vec(&glob_str);
 
+        let index = SearchIndex::open(chat_path.as_path(), &paths).expect("can build search index");
         let model = create_models(paths);
 
         let api = Api::from_token(api_token)?;
@@ -61,7 +194,11 @@ impl Bot {
             api: api,
             listener: listener,
             persistence: persistence,
-            rand: unseeded_rng()
+            rand: unseeded_rng(),
+            generation: GenerationConfig::default(),
+            search: SearchConfig::default(),
+            index: index,
+            groups: HashMap::default(),
         })
     }
 
@@ -69,74 +206,150 @@ impl Bot {
         use self::Response::*;
 
         let model = &self.model;
-        let persistence_path = self.persistence.root_path.to_str().unwrap();
         let persistence = &self.persistence;
         let api = &self.api;
         let rng = &mut self.rand;
+        let generation = &self.generation;
+        let search = &self.search;
+        let groups = &mut self.groups;
+        let index = &mut self.index;
 
-        self.listener.listen(|u| {
+        let result = self.listener.listen(|u| {
             match u.message {
-                Some(Message { 
-                    msg: MessageType::Text(t),
-                    chat: Chat::Group {id:group_id, .. }, 
-                    from,
-                    .. }) => {
-                    match handle(&from, group_id as u64, &t, &model, rng, persistence_path) {
-                        Reply { msg, parse_mode } => {
-                            match api.send_message(group_id, msg, parse_mode, None, None, None) {
-                                Ok(_) => (),
-                                Err(e) => println!("send message error -> {:?}", e),
+                Some(message) => {
+                    let (group_id, t) = match (&message.chat, &message.msg) {
+                        (&Chat::Group { id, .. }, &MessageType::Text(ref t)) => (id, t.clone()),
+                        _ => return Ok(ListeningAction::Continue),
+                    };
+                    let group_state = groups.entry(group_id as u64).or_insert_with(GroupState::default);
+                    match handle(&message, group_id as u64, &t, &model, rng, generation, search, group_state, index) {
+                        Reply { msg } => {
+                            for part in msg.split(MAX_MESSAGE_LENGTH) {
+                                match api.send_message(group_id, part, Some(ParseMode::Html), None, None, None) {
+                                    Ok(_) => (),
+                                    Err(e) => error!("send message error -> {:?}", e),
+                                }
                             }
                         },
-                        Store { user_id, group_id, text } => {
-                            persistence.store_chat_message(group_id, user_id, &text).expect("can persist chat message")
+                        Store { group_id, entry } => {
+                            let (path, offset, len) = persistence.store_chat_message(group_id, &entry).expect("can persist chat message");
+                            if let Err(e) = index.add_message(&path, offset, len, &entry) {
+                                error!("couldn't index message -> {:?}", e);
+                            }
+                            if let Err(e) = index.save_if_due() {
+                                error!("couldn't save search index -> {:?}", e);
+                            }
                         }
                     }
                 },
                 _ => (),        
             }
             Ok(ListeningAction::Continue)
-        })
+        });
+
+        // whatever came in since the last save, so a restart has less to catch up on
+        if index.has_unsaved() {
+            if let Err(e) = index.save() {
+                error!("couldn't save search index -> {:?}", e);
+            }
+        }
+        result
     }
 }
 
 
 pub enum Response {
-    Reply { msg: String, parse_mode: Option<ParseMode> },
-    Store { user_id: u64, group_id: u64, text: String}
+    Reply { msg: Html },
+    Store { group_id: u64, entry: LogEntry }
 }
 
-pub fn handle(user:&User, group_id: u64, msg:&str, model:&Model, rand: &mut XorShiftRng, persistence_path: &str) -> Response {
+// everything worth keeping about a message, for the log
+pub fn log_entry_for(message:&Message, text:&str) -> LogEntry {
+    LogEntry {
+        user_id: message.from.id.abs() as u64,
+        text: String::from(text),
+        timestamp: Some(message.date),
+        message_id: Some(message.message_id),
+        reply_to: message.reply.as_ref().map(|r| r.message_id),
+        username: message.from.username.clone(),
+        forward_from: message.forward.as_ref().map(|&(ref from, _)| from.id.abs() as u64),
+    }
+}
+
+pub fn handle(message:&Message, group_id: u64, msg:&str, model:&Model, rand: &mut XorShiftRng, generation: &GenerationConfig, search: &SearchConfig, group: &mut GroupState, index: &SearchIndex) -> Response {
     use self::Response::*;
     use self::ChatCommand::*;
     // use self::ChatModel::*;
 
-    let words : Vec<String> = msg.trim().splitn(2, ' ').map(|t|t.to_lowercase()).collect();
+    let mut words : Vec<String> = msg.trim().splitn(2, ' ').map(|t|t.to_lowercase()).collect();
     let command = words.first().and_then(|text| parse_command(text));
 
-    let user_id = user.id.abs() as u64;
+    let user_id = message.from.id.abs() as u64;
 
     if let Some(cmd) = command {
-        
+        let seeded = match cmd {
+            Roll | Generate(_) | Finish(_) | Haiku(_) | Convo => true,
+            _ => false,
+        };
+
+        // seeded commands get their own rng, from a seed that can be handed back to replay them
+        let mut seed : Seed = rand.next_u32();
+        if seeded {
+            if let Some(args) = words.get(1).cloned() {
+                match take_seed(&args) {
+                    Ok((maybe_seed, rest)) => {
+                        words[1] = rest;
+                        seed = maybe_seed.unwrap_or(seed);
+                    },
+                    Err(e) => return Reply { msg: Html::plain(&e) },
+                }
+            }
+            let replay = words.iter().filter(|w| !w.is_empty()).cloned().collect::<Vec<_>>().join(" ");
+            group.last_seeded = Some((replay, seed));
+        }
+        let rand = &mut seeded_rng(seed);
 
         match cmd {
+            Explain => {
+                match group.last_seeded {
+                    Some((ref replay, seed)) => {
+                        let mut msg = Html::plain(&format!("Last one was seed {}, replay with: ", seed));
+                        msg.code(&format!("{} seed:{}", replay, seed));
+                        Reply { msg: msg }
+                    },
+                    None => Reply { msg: Html::plain("Nothing to explain yet") },
+                }
+            },
             Roll => {
                 if let Some(dice) = words.get(1).and_then(|text| parse_dice(&text)) {
                     let rolls : Vec<String> = dice.roll(rand).iter().map(|n| format!("{}", n) ).collect();
                     let roll_text = rolls.join(" ");
-                    Reply { msg: format!("Rolled {}: {}", dice.to_string(), roll_text), parse_mode: None }
+                    let mut msg = Html::plain(&format!("Rolled {}: ", dice.to_string()));
+                    msg.bold(&roll_text).text(&format!(" (seed {})", seed));
+                    Reply { msg: msg }
                 } else {
 
-                    Reply { msg: format!("Invalid dice"), parse_mode: None }
+                    Reply { msg: Html::plain("Invalid dice") }
                 }
             }
-            Help => Reply { msg: String::from(HELP_MESSAGE), parse_mode: None },
+            Help => Reply { msg: Html::plain(HELP_MESSAGE) },
             Generate(gen_mode) => {
                 let (user_name, cm) = get_generative_model(model, &gen_mode, user_id, rand);
 
-                let sentence_start = vec!(Token::Start);
-                let message = generate(&model, rand, &sentence_start, &cm);
-                Reply { msg: format!("{}: {}", user_name, message), parse_mode: None }
+                let whatever = String::new();
+                let constraint_text : &str = words.get(1).unwrap_or(&whatever);
+                let compiled = parse_constraints(constraint_text).and_then(|c| c.compile(model));
+
+                match compiled {
+                    Ok(constraints) => {
+                        let sentence_start = vec!(Token::Start);
+                        let message = generate_best(&model, rand, &sentence_start, &cm, generation, &constraints)
+                            .map(|c| generate_sentence(&c.line))
+                            .unwrap_or_else(|| String::from(if constraints.is_unconstrained() { NOTHING_NEW } else { NOTHING_FITS }));
+                        Reply { msg: said(&user_name, &message) }
+                    },
+                    Err(e) => Reply { msg: Html::plain(&e) },
+                }
             },
             Finish(gen_mode) =>  {
                 let (user_name, cm) = get_generative_model(model, &gen_mode, user_id, rand);
@@ -145,48 +358,187 @@ pub fn handle(user:&User, group_id: u64, msg:&str, model:&Model, rand: &mut XorS
                 let sentence_text : &str = words.get(1).unwrap_or(&whatever);
                 let mut tokens = tokenize_line(sentence_text.to_lowercase().as_str());
                 tokens.pop(); // remove the end
-                let message = generate(&model, rand, &tokens, &cm);
-                Reply { msg: format!("{}: {}", user_name, message), parse_mode: None }
+                let resolved = match resolve_start(model, cm, &tokens) {
+                    Ok(resolved) => resolved,
+                    Err(e) => return Reply { msg: Html::plain(&e) },
+                };
+                let message = generate_best(&model, rand, &resolved.context, &cm, generation, &Unsteered)
+                    .map(|c| generate_sentence(&resolved.splice(&c)))
+                    .unwrap_or_else(|| String::from(NOTHING_NEW));
+
+                let notes : Vec<String> = resolved.substitutions.iter().map(|&(ref unknown, ref known)| {
+                    format!("never heard \"{}\", went with \"{}\"", unknown, known)
+                }).collect();
+
+                let mut msg = said(&user_name, &message);
+                if !notes.is_empty() {
+                    msg.line().italic(&format!("({})", notes.join(", ")));
+                }
+                Reply { msg: msg }
+            },
+            Haiku(gen_mode) => {
+                let (user_name, cm) = get_generative_model(model, &gen_mode, user_id, rand);
+
+                let message = generate_poem(&model, rand, &cm, generation, &HAIKU)
+                    .map(|lines| lines.join("\n"))
+                    .unwrap_or_else(|| String::from(NOTHING_FITS));
+                let mut msg = Html::new();
+                msg.bold(&format!("{}:", user_name)).line().text(&message);
+                Reply { msg: msg }
+            },
+            Convo => {
+                let whatever = String::new();
+                let convo_text : &str = words.get(1).unwrap_or(&whatever);
+                match parse_convo(convo_text) {
+                    Ok(request) => {
+                        let mut msg = Html::new();
+                        for (speaker, line) in generate_convo(&model, rand, generation, &request) {
+                            msg.append(said(&username_for_id(speaker), &line)).line();
+                        }
+                        if msg.is_empty() {
+                            Reply { msg: Html::plain("Nobody had anything to say") }
+                        } else {
+                            Reply { msg: msg }
+                        }
+                    },
+                    Err(e) => Reply { msg: Html::plain(&e) },
+                }
+            },
+            Search => {
+                // the query is taken as typed, OR is only an operator in capitals
+                let query_text = msg.trim().splitn(2, ' ').nth(1).unwrap_or("");
+                let mut query = match parse_query(query_text, user_id) {
+                    Ok(query) => query,
+                    Err(e) => return Reply { msg: Html::plain(&e) },
+                };
+                index.expand(&mut query, search);
+
+                let mut found = index.search(&query);
+                index.rank(&mut found, &query, search);
+
+                let mut page = SearchPage { query: String::from(query_text), terms: query.terms(), docs: found, shown: 0 };
+                let message = next_search_page(index, &mut page, search, group_id);
+                group.last_search = Some(page);
+
+                Reply { msg: message }
+            },
+            Count => {
+                let query_text = msg.trim().splitn(2, ' ').nth(1).unwrap_or("");
+                match group_matches(index, query_text, user_id, group_id, search) {
+                    Ok((query, docs)) => {
+                        let counts = count_by_user(index, &docs, &query);
+                        if counts.is_empty() {
+                            return Reply { msg: Html::plain(&format!("Nobody here has said {}", query_text)) }
+                        }
+                        let mut msg = Html::new();
+                        msg.text("Who says ").bold(query_text).line();
+                        for (user_id, n, messages) in counts {
+                            msg.bold(&format!("{}:", username_for_id(user_id))).text(&format!(" {} ({} messages)", n, messages)).line();
+                        }
+                        Reply { msg: msg }
+                    },
+                    Err(e) => Reply { msg: Html::plain(&e) },
+                }
+            },
+            Trend => {
+                let query_text = msg.trim().splitn(2, ' ').nth(1).unwrap_or("");
+                match group_matches(index, query_text, user_id, group_id, search) {
+                    Ok((query, docs)) => {
+                        let months = count_by_month(index, &docs, &query);
+                        let (peak, peak_count) = match months.iter().max_by_key(|&&(_, n)| n) {
+                            Some(&(month, n)) => (month, n),
+                            None => return Reply { msg: Html::plain(&format!("Nobody here has said {} (in a dated log)", query_text)) },
+                        };
+                        let values : Vec<u32> = months.iter().map(|&(_, n)| n).collect();
+                        let mut msg = Html::new();
+                        msg.bold(query_text).text(&format!(" by month, {} to {}", format_month(months[0].0), format_month(months[months.len() - 1].0))).line()
+                            .code(&sparkline(&values)).line()
+                            .text(&format!("peak {} ({})", format_month(peak), peak_count));
+                        Reply { msg: msg }
+                    },
+                    Err(e) => Reply { msg: Html::plain(&e) },
+                }
+            },
+            Grep => {
+                let grep_text = msg.trim().splitn(2, ' ').nth(1).unwrap_or("");
+                let found = parse_grep(grep_text, user_id)
+                    .and_then(|(pattern, users)| compile_pattern(&pattern).map(|regex| (pattern, regex, users)))
+                    .and_then(|(pattern, regex, users)| {
+                        grep(index, &regex, &users, group_id, &GrepLimits::default())
+                            .map(|outcome| (pattern, outcome))
+                            .map_err(|e| {
+                                error!("grep failed -> {:?}", e);
+                                String::from("Couldn't read the logs")
+                            })
+                    });
+
+                match found {
+                    Ok((pattern, outcome)) => {
+                        let mut msg = Html::new();
+                        msg.text("Newest matches for ").code(&pattern).line().line();
+                        for found in &outcome.matches {
+                            let date = found.date.map(|d| d.format("%Y-%m-%d").to_string()).unwrap_or_else(|| String::from("imported history"));
+                            msg.italic(&date).line()
+                                .bold(&format!("{}:", username_for_id(found.user_id))).text(" ").marked(&found.text, &found.spans).line().line();
+                        }
+                        if outcome.matches.is_empty() {
+                            msg.text("Nothing").line();
+                        }
+                        if let Some(reason) = outcome.cut_short {
+                            msg.italic(&format!("(stopped early, {})", reason));
+                        }
+                        Reply { msg: msg }
+                    },
+                    Err(e) => Reply { msg: Html::plain(&e) },
+                }
             },
-            Search(maybe_user) => {
-                let user_ids = user_ids_for_chat_model(&maybe_user);
-                let whatever = "".into();
-                let sentence_text : &String = words.get(1).unwrap_or(&whatever);
-                let terms = terms_for_search(sentence_text);
-
-                let glob_str = format!("{}/**/*.log", persistence_path);
-                let search_paths = glob_vec(&glob_str);
-                
-                let results = search(search_paths, &terms, &user_ids);
-                let total = results.len();
-
-                let mut message : String = format!("Searched for {:?} found {} results\n\n", terms, total);
-
-                for result in results.iter().take(10) {
-                    let user_name = username_for_id(result.user_id);
-                    let some_shit = format!("{}: {}\n\n", user_name, pretty_search_result(&result.full_text, &terms));
-                    message.push_str(&some_shit);
-                } 
-
-                Reply { msg: message, parse_mode: Some(ParseMode::Html) }
+            More => {
+                match group.last_search {
+                    Some(ref mut page) if page.shown < page.docs.len() => {
+                        Reply { msg: next_search_page(index, page, search, group_id) }
+                    },
+                    Some(_) => Reply { msg: Html::plain("That's all of them") },
+                    None => Reply { msg: Html::plain("Nothing to show more of, /search first") },
+                }
             },
         }
     } else {
-        Store { user_id: user_id, group_id: group_id, text: String::from(msg) }
+        Store { group_id: group_id, entry: log_entry_for(message, msg) }
     }
 }
 
+const NOTHING_NEW: &'static str = "... (only had things you've already said)";
+const NOTHING_FITS: &'static str = "... (couldn't come up with anything that fits)";
+
 const HELP_MESSAGE: &'static str = r#"
 ctx: me|hydra|robe|mikel|michael
 
 /roll 1d6
     Roll some dice bitch
 
-/search
-    Search all users
+/explain
+    Seed for the last /roll /gen /finish /haiku or /convo, add seed:1234 to any of them to replay
+
+/search pizza "deep dish" -pineapple ~restaraunt
+    Search all users, other forms of words ("quote" a word for just that), "phrases", -words to leave out and ~words to allow typos
+
+/search cat OR dog from:robe after:2016-03 before:2017
+    Either word, from:{me|robe|mikel|michael}, dates (day, month or year) of the log
+
+/search pizza sort:new
+    Best matches first (favouring recent ones), or sort:new / sort:old
+
+/more
+    Next page of the last search
 
-/search_{robe|mikel|michael}
-    Search a specific users
+/count literally
+    Who in here says it the most, takes the same words and filters as /search
+
+/trend literally
+    How often it's been said here each month
+
+/grep pi+zza from:robe
+    Newest messages here matching a regular expression, case insensitive unless it starts with (?-i)
 
 /gen|/poke
     Sentence for random User
@@ -194,6 +546,15 @@ ctx: me|hydra|robe|mikel|michael
 /gen_{ctx}
     Setence for contextual user
 
+/gen_{ctx} with:pizza rhyme:cat words:8
+    Sentence containing a word, ending on a rhyme, with a word count
+
+/haiku_{ctx}
+    5/7/5 haiku for contextual user
+
+/convo robe mikel 6
+    Conversation between users, add "turns" to pick speakers by who usually replies to who
+
 /finish <sentence start>
     Finish sentence for random user
 
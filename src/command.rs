@@ -2,7 +2,7 @@
 extern crate rand;
 
 use rand::Rng;
-use super::{HashMap, HashSet};
+use super::{HashMap, Seed};
 use super::model::*;
 use super::generate::choose_user;
 
@@ -36,17 +36,32 @@ pub fn user_id_for_casual(name:&str) -> Option<UserId> {
     USER_LOOKUP_CASUAL.get(name).cloned()
 }
 
+// the name we know them by, or failing that the one they logged with
+pub fn display_name(id:UserId, username:Option<&str>) -> String {
+    USER_LOOKUP.get(&id).cloned()
+        .or_else(|| username.map(|u| format!("@{}", u)))
+        .unwrap_or_else(|| format!("user {}", id))
+}
+
+// search context can turn up people the bot has no name for
 pub fn username_for_id(id:UserId) -> String {
-    USER_LOOKUP.get(&id).unwrap().clone()
+    USER_LOOKUP.get(&id).cloned().unwrap_or_else(|| format!("user {}", id))
 }
 
 #[derive(Eq, PartialEq, Clone, Debug)]
 pub enum ChatCommand {
     Help,
-    Search(Option<String>),
+    Search,
     Generate(ChatModel),
     Finish(ChatModel),
+    Haiku(ChatModel),
+    Convo,
     Roll,
+    Explain,
+    More,
+    Count,
+    Trend,
+    Grep,
 }
 
 #[derive(Eq, PartialEq, Debug, Clone)]
@@ -58,14 +73,6 @@ pub enum ChatModel {
 }
 
 
-pub fn parse_user(model: &str) -> Option<String> {
-    if USER_LOOKUP_CASUAL.contains_key(model) {
-        Some(String::from(model))
-    } else {
-        None
-    }
-}
-
 pub fn parse_model(model: &str) -> Option<ChatModel> {
     if model == "me" {
         Some(ChatModel::Me)
@@ -80,22 +87,6 @@ pub fn parse_model(model: &str) -> Option<ChatModel> {
     }
 }
 
-pub fn user_ids_for_chat_model(username: &Option<String>) -> HashSet<UserId> {
-    let mut user_ids : HashSet<UserId> = HashSet::default();
-    
-    if let &Some(ref name) = username {
-        let user_id = user_id_for_casual(name).unwrap();
-        user_ids.insert(user_id);
-    } else {
-        for user_id in all_user_ids() {
-            user_ids.insert(user_id);
-        } 
-    }
-
-    user_ids
-}
-
-
 pub fn parse_command(command: &str) -> Option<ChatCommand> {
     let parts : Vec<&str> = command.split("_").collect();
 
@@ -103,19 +94,32 @@ pub fn parse_command(command: &str) -> Option<ChatCommand> {
         (Some(&"/roll"), _) => {
            Some(ChatCommand::Roll)
         }
+        (Some(&"/explain"), _) => {
+            Some(ChatCommand::Explain)
+        }
+        (Some(&"/more"), _) => {
+            Some(ChatCommand::More)
+        }
         (Some(&"/help"), _) => {
             Some(ChatCommand::Help)
         }
-        (Some(&"/search"), maybe_model) => {
-            let maybe_user = maybe_model.and_then(|m| parse_user(m));
-            Some(ChatCommand::Search(maybe_user))
+        (Some(&"/search"), _) => {
+            Some(ChatCommand::Search)
         }
+        (Some(&"/count"), _) => Some(ChatCommand::Count),
+        (Some(&"/trend"), _) => Some(ChatCommand::Trend),
+        (Some(&"/grep"), _) => Some(ChatCommand::Grep),
         (Some(&"/hydra"), _) => Some(ChatCommand::Generate(ChatModel::All)),
         (Some(&"/poke"), _) => Some(ChatCommand::Generate(ChatModel::Random)),
         (Some(&"/finish"), maybe_model) => {
             let some_shit = maybe_model.and_then(|m| parse_model(m)).unwrap_or(ChatModel::Random);
             Some(ChatCommand::Finish(some_shit))
         },
+        (Some(&"/convo"), _) => Some(ChatCommand::Convo),
+        (Some(&"/haiku"), maybe_model) => {
+            let some_shit = maybe_model.and_then(|m| parse_model(m)).unwrap_or(ChatModel::Random);
+            Some(ChatCommand::Haiku(some_shit))
+        },
         (Some(&"/gen"), maybe_model) => {
             let some_shit = maybe_model.and_then(|m| parse_model(m)).unwrap_or(ChatModel::Random);
             Some(ChatCommand::Generate(some_shit))
@@ -124,6 +128,44 @@ pub fn parse_command(command: &str) -> Option<ChatCommand> {
     }
 }
 
+fn is_space(c:char) -> bool {
+    c.is_whitespace() && c != '\n'
+}
+
+// pulls a "seed:1234" argument out of a command's arguments, returning the rest as it was written, line breaks and all
+pub fn take_seed(args:&str) -> Result<(Option<Seed>, String), String> {
+    let mut seed = None;
+    let mut rest = String::with_capacity(args.len());
+    let mut at = 0;
+
+    while at < args.len() {
+        let start = args[at..].find(|c:char| !c.is_whitespace()).map(|i| at + i).unwrap_or(args.len());
+        let end = args[start..].find(char::is_whitespace).map(|i| start + i).unwrap_or(args.len());
+        let arg = &args[start..end];
+
+        if arg.starts_with("seed:") {
+            match arg["seed:".len()..].parse::<Seed>() {
+                Ok(n) => seed = Some(n),
+                Err(_) => return Err(format!("seed: wants a number, not {:?}", arg)),
+            }
+            // it goes along with the spaces before it, or after it when it starts a line
+            rest.push_str(&args[at..start]);
+            let kept = rest.trim_right_matches(is_space).len();
+            rest.truncate(kept);
+            at = if rest.is_empty() || rest.ends_with('\n') {
+                args[end..].find(|c:char| !is_space(c)).map(|i| end + i).unwrap_or(args.len())
+            } else {
+                end
+            };
+        } else {
+            rest.push_str(&args[at..end]);
+            at = end;
+        }
+    }
+
+    Ok((seed, rest))
+}
+
 pub fn get_generative_model<'a, R : Rng>(m: &'a Model, chat_model:&ChatModel, user_id: UserId, rng: &mut R) -> (String, &'a UserGenerativeModel) {
     match chat_model {
         &ChatModel::Me => (username_for_id(user_id).clone(), &m.users[&user_id]),
@@ -0,0 +1,192 @@
+use super::HashSet;
+use super::model::*;
+use super::generate::{Steering, Candidate};
+use super::tokenizer::Token;
+
+// how strongly a still missing required word is favoured when a context offers it
+const REQUIRED_BOOST : f64 = 25.0;
+const RHYME_BOOST : f64 = 4.0;
+// words are discouraged, not forbidden, once the target is met, so the line can still find an End
+const PAST_TARGET_WEIGHT : f64 = 0.05;
+
+// constraints for /gen, e.g. "with:pizza rhyme:cat words:8"
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Constraints {
+    pub required: Vec<String>,
+    pub rhyme: Option<String>,
+    pub words: Option<usize>,
+}
+
+pub fn parse_constraints(text:&str) -> Result<Constraints, String> {
+    let mut constraints = Constraints::default();
+
+    for arg in text.to_lowercase().split_whitespace() {
+        let parts : Vec<&str> = arg.splitn(2, ':').collect();
+        match (parts[0], parts.get(1)) {
+            ("with", Some(word)) if !word.is_empty() => constraints.required.push(String::from(*word)),
+            ("rhyme", Some(word)) if !word.is_empty() => constraints.rhyme = Some(String::from(*word)),
+            ("words", Some(n)) => {
+                match n.parse::<usize>() {
+                    Ok(n) if n > 0 && n < 25 => constraints.words = Some(n),
+                    _ => return Err(format!("words: wants a number from 1 to 24, not {:?}", n)),
+                }
+            },
+            _ => return Err(format!("don't understand {:?}, try with:word rhyme:word words:n", arg)),
+        }
+    }
+
+    Ok(constraints)
+}
+
+fn is_vowel(c:char) -> bool {
+    "aeiouy".contains(c)
+}
+
+// words whose spelling doesn't say how they end, keyed to a regular word they rhyme with
+const IRREGULAR_RHYMES : &'static [(&'static str, &'static str)] = &[
+    ("some", "um"), ("come", "um"), ("done", "un"), ("none", "un"), ("one", "un"), ("gone", "on"),
+    ("love", "uv"), ("dove", "uv"), ("glove", "uv"), ("shove", "uv"), ("above", "uv"),
+    ("move", "oov"), ("prove", "oov"), ("lose", "ooz"), ("whose", "ooz"),
+    ("give", "iv"), ("live", "iv"), ("have", "av"), ("were", "ur"), ("there", "air"), ("where", "air"),
+    ("to", "oo"), ("do", "oo"), ("who", "oo"), ("two", "oo"),
+];
+
+// everything from the last vowel group on, "night" -> "ight". a silent e stays on the end
+// without counting as the vowel, so "make" is "ake", and a word ending in its vowel
+// is keyed by that alone, so "me" and "be" rhyme
+pub fn rhyme_key(word:&str) -> String {
+    if let Some(&(_, key)) = IRREGULAR_RHYMES.iter().find(|&&(irregular, _)| irregular == word) {
+        return String::from(key)
+    }
+    let chars : Vec<char> = word.chars().collect();
+    let n = chars.len();
+    let silent_e = n > 2 && chars[n - 1] == 'e' && !is_vowel(chars[n - 2]) && chars[..n - 2].iter().any(|&c| is_vowel(c));
+
+    let mut end = if silent_e { n - 1 } else { n };
+    while end > 0 && !is_vowel(chars[end - 1]) {
+        end -= 1;
+    }
+    let mut start = end;
+    while start > 0 && is_vowel(chars[start - 1]) {
+        start -= 1;
+    }
+    chars[start..].iter().cloned().collect()
+}
+
+pub fn rhymes_with(word:&str, target:&str) -> bool {
+    word != target && rhyme_key(word) == rhyme_key(target)
+}
+
+fn word_count(line:&Line) -> usize {
+    line.iter().filter(|t| match *t { &Token::Word(_) => true, _ => false }).count()
+}
+
+fn last_word(line:&Line) -> Option<&Token> {
+    line.iter().rev().find(|t| match *t { &Token::Word(_) => true, _ => false })
+}
+
+// constraints resolved against a model's vocabulary, ready to steer generation
+pub struct CompiledConstraints {
+    pub required: Vec<Token>,
+    pub rhyme: Option<String>,
+    pub rhymes: HashSet<TokenIdx>,
+    pub words: Option<usize>,
+}
+
+impl Constraints {
+    pub fn compile(&self, model:&Model) -> Result<CompiledConstraints, String> {
+        let mut required = Vec::new();
+        for word in &self.required {
+            let token = Token::Word(word.clone());
+            if !model.token_to_idx.contains_key(&token) {
+                return Err(format!("nobody has ever said {:?}", word))
+            }
+            required.push(token);
+        }
+
+        let mut rhymes = HashSet::default();
+        if let Some(ref target) = self.rhyme {
+            for (idx, token) in model.tokens.iter().enumerate() {
+                if let &Token::Word(ref word) = token {
+                    if rhymes_with(word, target) {
+                        rhymes.insert(idx);
+                    }
+                }
+            }
+            if rhymes.is_empty() {
+                return Err(format!("nothing rhymes with {:?}", target))
+            }
+        }
+
+        Ok(CompiledConstraints {
+            required: required,
+            rhyme: self.rhyme.clone(),
+            rhymes: rhymes,
+            words: self.words,
+        })
+    }
+}
+
+impl CompiledConstraints {
+    pub fn is_unconstrained(&self) -> bool {
+        self.required.is_empty() && self.rhyme.is_none() && self.words.is_none()
+    }
+
+    fn has_required(&self, line:&Line) -> bool {
+        self.required.iter().all(|t| line.contains(t))
+    }
+
+    fn has_rhyme(&self, line:&Line) -> bool {
+        match (&self.rhyme, last_word(line)) {
+            (&Some(ref target), Some(&Token::Word(ref word))) => rhymes_with(word, target),
+            (&Some(_), _) => false,
+            (&None, _) => true,
+        }
+    }
+
+    fn has_words(&self, line:&Line) -> bool {
+        self.words.map(|target| word_count(line) == target).unwrap_or(true)
+    }
+}
+
+impl Steering for CompiledConstraints {
+    fn weight(&self, model:&Model, line:&Line, token_idx:TokenIdx) -> f64 {
+        let words = word_count(line);
+        let token = &model.tokens[token_idx];
+
+        match token {
+            &Token::End => {
+                let short = self.words.map(|target| words < target).unwrap_or(false);
+                if short || !self.has_required(line) || !self.has_rhyme(line) {
+                    0.0
+                } else {
+                    1.0
+                }
+            },
+            &Token::Word(_) => {
+                let mut weight = 1.0;
+                if self.required.contains(token) && !line.contains(token) {
+                    weight *= REQUIRED_BOOST;
+                }
+                if self.rhymes.contains(&token_idx) {
+                    let last_slot = self.words.map(|target| words + 1 == target).unwrap_or(true);
+                    if last_slot {
+                        weight *= RHYME_BOOST;
+                    }
+                }
+                if let Some(target) = self.words {
+                    if words >= target {
+                        weight *= PAST_TARGET_WEIGHT;
+                    }
+                }
+                weight
+            },
+            _ => 1.0,
+        }
+    }
+
+    fn accept(&self, candidate:&Candidate) -> bool {
+        let line = &candidate.line;
+        candidate.ended() && self.has_required(line) && self.has_rhyme(line) && self.has_words(line)
+    }
+}
@@ -0,0 +1,130 @@
+use rand::Rng;
+
+use super::HashSet;
+use super::model::*;
+use super::generate::*;
+use super::command::*;
+use super::tokenizer::Token;
+
+pub const DEFAULT_CONVO_LINES : usize = 6;
+pub const MAX_CONVO_LINES : usize = 12;
+
+// how much a word from the line being replied to is favoured
+const ECHO_BOOST : f64 = 3.0;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TurnTaking {
+    RoundRobin,
+    // next speaker drawn from who tends to reply after whom
+    Learned,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConvoRequest {
+    pub speakers: Vec<UserId>,
+    pub lines: usize,
+    pub turn_taking: TurnTaking,
+}
+
+// "/convo robe mikel 6", "/convo robe mikel michael 8 turns", "/convo turns"
+pub fn parse_convo(args:&str) -> Result<ConvoRequest, String> {
+    let mut speakers : Vec<UserId> = Vec::new();
+    let mut lines = DEFAULT_CONVO_LINES;
+    let mut turn_taking = TurnTaking::RoundRobin;
+
+    for arg in args.split_whitespace() {
+        if arg == "turns" {
+            turn_taking = TurnTaking::Learned;
+        } else if let Ok(n) = arg.parse::<usize>() {
+            if n == 0 || n > MAX_CONVO_LINES {
+                return Err(format!("a convo can be 1 to {} lines", MAX_CONVO_LINES))
+            }
+            lines = n;
+        } else if let Some(user_id) = user_id_for_casual(arg) {
+            if !speakers.contains(&user_id) {
+                speakers.push(user_id);
+            }
+        } else {
+            return Err(format!("who's {:?}? try {}", arg, casual_usernames().join(", ")))
+        }
+    }
+
+    if speakers.is_empty() {
+        speakers = all_user_ids();
+        speakers.sort();
+        speakers.dedup();
+    } else if speakers.len() < 2 {
+        return Err(String::from("a convo needs at least two people"))
+    }
+
+    Ok(ConvoRequest { speakers: speakers, lines: lines, turn_taking: turn_taking })
+}
+
+// favours words from the line being replied to, so replies stay roughly on topic
+pub struct Echo {
+    pub previous: HashSet<Token>,
+}
+
+impl Steering for Echo {
+    fn weight(&self, model:&Model, line:&Line, token_idx:TokenIdx) -> f64 {
+        let token = &model.tokens[token_idx];
+        match token {
+            &Token::Word(_) if self.previous.contains(token) && !line.contains(token) => ECHO_BOOST,
+            _ => 1.0,
+        }
+    }
+}
+
+pub fn next_speaker<R : Rng>(model:&Model, request:&ConvoRequest, turn:usize, current:Option<UserId>, rng: &mut R) -> UserId {
+    let speakers = &request.speakers;
+    match (&request.turn_taking, current) {
+        (&TurnTaking::Learned, Some(current)) => {
+            let followers : Vec<(UserId, OccurenceCount)> = model.turns.get(&current).map(|counts| {
+                speakers.iter().filter(|&&s| s != current).map(|&s| (s, counts.get(&s).cloned().unwrap_or(0))).collect()
+            }).unwrap_or_else(Vec::new);
+
+            let total : OccurenceCount = followers.iter().map(|&(_, n)| n).sum();
+            if total > 0 {
+                let mut n = rng.gen_range(0, total);
+                for &(speaker, count) in &followers {
+                    if n < count {
+                        return speaker
+                    }
+                    n -= count;
+                }
+            }
+            let others : Vec<UserId> = speakers.iter().cloned().filter(|&s| s != current).collect();
+            if others.is_empty() { current } else { others[rng.gen_range(0, others.len())] }
+        },
+        (&TurnTaking::Learned, None) => speakers[rng.gen_range(0, speakers.len())],
+        (&TurnTaking::RoundRobin, _) => speakers[turn % speakers.len()],
+    }
+}
+
+pub fn generate_convo<R : Rng>(model:&Model, rng: &mut R, config:&GenerationConfig, request:&ConvoRequest) -> Vec<(UserId, String)> {
+    let sentence_start = vec!(Token::Start);
+    let mut out : Vec<(UserId, String)> = Vec::with_capacity(request.lines);
+    let mut previous : Line = Vec::new();
+    let mut current : Option<UserId> = None;
+
+    for turn in 0..request.lines {
+        let speaker = next_speaker(model, request, turn, current, rng);
+        current = Some(speaker);
+
+        let user_model = match model.users.get(&speaker) {
+            Some(m) => m,
+            None => continue, // never said anything
+        };
+
+        let echo = Echo { previous: previous.iter().cloned().collect() };
+        match generate_best(model, rng, &sentence_start, user_model, config, &echo) {
+            Some(candidate) => {
+                out.push((speaker, generate_sentence(&candidate.line)));
+                previous = candidate.line;
+            },
+            None => out.push((speaker, String::from("..."))),
+        }
+    }
+
+    out
+}
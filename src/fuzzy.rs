@@ -0,0 +1,94 @@
+// levenshtein distance over chars
+pub fn edit_distance(a:&str, b:&str) -> usize {
+    let a : Vec<char> = a.chars().collect();
+    let b : Vec<char> = b.chars().collect();
+
+    let mut previous : Vec<usize> = (0..b.len() + 1).collect();
+    let mut current : Vec<usize> = vec![0; b.len() + 1];
+
+    for i in 0..a.len() {
+        current[0] = i + 1;
+        for j in 0..b.len() {
+            let substitution = previous[j] + if a[i] == b[j] { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        ::std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()]
+}
+
+pub fn common_suffix_len(a:&str, b:&str) -> usize {
+    a.chars().rev().zip(b.chars().rev()).take_while(|&(x, y)| x == y).count()
+}
+
+// how far apart two words can be and still count as a typo of each other
+pub fn max_typo_distance(word:&str) -> usize {
+    let n = word.chars().count();
+    if n <= 2 {
+        0
+    } else if n <= 5 {
+        1
+    } else {
+        2
+    }
+}
+
+// closest candidate within typo distance, earlier candidates win ties
+pub fn nearest_by_edit<'a, I>(word:&str, candidates: I) -> Option<(&'a str, usize)> where I : Iterator<Item=&'a str> {
+    let max = max_typo_distance(word);
+    let n = word.chars().count();
+    let mut best : Option<(&'a str, usize)> = None;
+
+    for candidate in candidates {
+        let m = candidate.chars().count();
+        let length_gap = if m > n { m - n } else { n - m };
+        if length_gap > max || candidate == word {
+            continue;
+        }
+        let d = edit_distance(word, candidate);
+        let better = d <= max && best.map(|(_, best_d)| d < best_d).unwrap_or(true);
+        if better {
+            best = Some((candidate, d));
+        }
+    }
+
+    best
+}
+
+// candidate sharing the longest ending (at least min_len chars), "skateboarding" -> "boarding"
+pub fn nearest_by_suffix<'a, I>(word:&str, candidates: I, min_len: usize) -> Option<&'a str> where I : Iterator<Item=&'a str> {
+    let mut best : Option<(&'a str, usize)> = None;
+
+    for candidate in candidates {
+        let shared = common_suffix_len(word, candidate);
+        let better = shared >= min_len && best.map(|(_, best_shared)| shared > best_shared).unwrap_or(true);
+        if better {
+            best = Some((candidate, shared));
+        }
+    }
+
+    best.map(|(candidate, _)| candidate)
+}
+
+// every candidate within typo distance, closest first
+pub fn within_typo_distance<'a, I>(word:&str, candidates: I) -> Vec<&'a str> where I : Iterator<Item=&'a str> {
+    let max = max_typo_distance(word);
+    let n = word.chars().count();
+    let mut close : Vec<(usize, &'a str)> = Vec::new();
+
+    for candidate in candidates {
+        let m = candidate.chars().count();
+        let length_gap = if m > n { m - n } else { n - m };
+        if length_gap > max || candidate == word {
+            continue;
+        }
+        let d = edit_distance(word, candidate);
+        if d <= max {
+            close.push((d, candidate));
+        }
+    }
+
+    close.sort();
+    close.into_iter().map(|(_, candidate)| candidate).collect()
+}
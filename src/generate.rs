@@ -2,9 +2,12 @@ extern crate rand;
 
 use super::model::*;
 use rand::Rng;
-use super::tokenizer::{Token};
-use super::HashMap;
+use super::tokenizer::{Token, NumberKind, punctuation_class, ends_sentence, is_ideograph, normalize_token};
+use super::trace;
+use super::fuzzy::*;
 use std::hash::Hash;
+use std::collections::BTreeMap;
+use rustc_serialize::json::{Json, ToJson};
 
 pub type BigramDebug = Vec<(Token, OccurenceCount, Vec<(Token, f64)>)>;
 pub type TrigramDebug = Vec<((Token, Token), OccurenceCount, Vec<(Token, f64)>)>;
@@ -24,7 +27,294 @@ impl Default for GenerationDebugInfo {
     }
 }
 
-pub fn generate<R : Rng>(model:&Model, rng: &mut R, sentence_start:&Vec<Token>, user_model:&UserGenerativeModel) -> String { // -> (UserId, String, GenerationDebugInfo)
+pub const MAX_LINE_LENGTH : usize = 30;
+
+// log probability charged for the fallback End when no table had anything to offer
+const LAST_RESORT_LOG_P : f64 = -7.0;
+
+#[derive(Debug, Clone)]
+pub struct ScoringWeights {
+    pub length: f64,
+    pub likelihood: f64,
+    pub novelty: f64,
+    pub ended: f64,
+}
+
+impl Default for ScoringWeights {
+    fn default() -> ScoringWeights {
+        ScoringWeights {
+            length: 1.0,
+            likelihood: 0.5,
+            novelty: 1.0,
+            ended: 1.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GenerationConfig {
+    // how many times to re-roll a line that turns out to be a copy of something said
+    pub max_resamples: usize,
+    // candidates generated per request, the best scoring one wins
+    pub best_of: usize,
+    // word count past which a candidate gets no more credit for length
+    pub ideal_words: usize,
+    pub weights: ScoringWeights,
+    // commands would trigger bots, and mentions ping real people
+    pub emit_commands: bool,
+    pub emit_mentions: bool,
+    pub emit_hashtags: bool,
+}
+
+impl Default for GenerationConfig {
+    fn default() -> GenerationConfig {
+        GenerationConfig {
+            max_resamples: 8,
+            best_of: 5,
+            ideal_words: 12,
+            weights: ScoringWeights::default(),
+            emit_commands: false,
+            emit_mentions: false,
+            emit_hashtags: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub line: Line,
+    // where the generated part of the line begins
+    pub start_len: usize,
+    pub log_likelihood: f64,
+    // generated tokens picked from a context with more than one option
+    pub branching: usize,
+}
+
+impl Candidate {
+    pub fn generated(&self) -> &[Token] {
+        &self.line[self.start_len..]
+    }
+
+    pub fn words(&self) -> usize {
+        self.generated().iter().filter(|t| match *t { &Token::Word(_) => true, _ => false }).count()
+    }
+
+    pub fn ended(&self) -> bool {
+        self.line.last() == Some(&Token::End)
+    }
+
+    pub fn score(&self, config:&GenerationConfig) -> f64 {
+        let steps = self.generated().len();
+        if steps == 0 {
+            return ::std::f64::NEG_INFINITY
+        }
+        let weights = &config.weights;
+        let length = (self.words().min(config.ideal_words) as f64) / (config.ideal_words.max(1) as f64);
+        let likelihood = self.log_likelihood / (steps as f64);
+        let novelty = (self.branching as f64) / (steps as f64);
+        let ended = if self.ended() { 1.0 } else { 0.0 };
+
+        weights.length * length + weights.likelihood * likelihood + weights.novelty * novelty + weights.ended * ended
+    }
+}
+
+// biases token choice during generation, and gets the final say on whether a candidate is usable
+pub trait Steering {
+    // multiplier on a token's occurrence count, 0 rules it out
+    fn weight(&self, _model:&Model, _line:&Line, _token_idx:TokenIdx) -> f64 {
+        1.0
+    }
+
+    fn accept(&self, _candidate:&Candidate) -> bool {
+        true
+    }
+}
+
+pub struct Unsteered;
+
+impl Steering for Unsteered {}
+
+// rules out token classes the config doesn't want emitted, on top of some other steering
+pub struct Filtered<'a> {
+    pub config: &'a GenerationConfig,
+    pub inner: &'a Steering,
+}
+
+impl<'a> Steering for Filtered<'a> {
+    fn weight(&self, model:&Model, line:&Line, token_idx:TokenIdx) -> f64 {
+        let allowed = match &model.tokens[token_idx] {
+            &Token::Command(_) => self.config.emit_commands,
+            &Token::Mention(_) => self.config.emit_mentions,
+            &Token::Hashtag(_) => self.config.emit_hashtags,
+            _ => true,
+        };
+        if allowed {
+            self.inner.weight(model, line, token_idx)
+        } else {
+            0.0
+        }
+    }
+
+    fn accept(&self, candidate:&Candidate) -> bool {
+        self.inner.accept(candidate)
+    }
+}
+
+// picks a value for a normalized number, weighted by how often each was actually said
+pub fn number_value<R : Rng>(model:&Model, rng: &mut R, kind:NumberKind) -> String {
+    match model.numbers.get(&kind) {
+        Some(values) if !values.is_empty() => {
+            let total : OccurenceCount = values.iter().map(|&(_, n)| n).sum();
+            let mut n = rng.gen_range(0, total);
+            for &(ref value, count) in values {
+                if n < count {
+                    return value.clone()
+                }
+                n -= count;
+            }
+            values[0].0.clone()
+        },
+        _ => String::from(kind.fallback()),
+    }
+}
+
+pub fn realize_numbers<R : Rng>(model:&Model, rng: &mut R, line:&mut Line) {
+    for token in line.iter_mut() {
+        let realized = match token {
+            &mut Token::Number(kind, ref value) if value.is_empty() => Some(Token::Number(kind, number_value(model, rng, kind))),
+            _ => None,
+        };
+        if let Some(realized) = realized {
+            *token = realized;
+        }
+    }
+}
+
+// a steered line that doesn't copy the user's real messages and that the steering accepts, rerolled until one is, None if it never managed
+pub fn generate_novel<R : Rng>(model:&Model, rng: &mut R, sentence_start:&Vec<Token>, user_model:&UserGenerativeModel, config:&GenerationConfig, steering:&Steering) -> Option<Candidate> {
+    for attempt in 0..(config.max_resamples + 1) {
+        let candidate = generate_steered(model, rng, sentence_start, user_model, steering);
+        let line_idxs = model.line_indices(&candidate.line);
+        if !user_model.own_lines.is_novel(&line_idxs, sentence_start.len()) {
+            debug!("attempt {} copied a training line -> {:?}", attempt, candidate.line);
+            continue;
+        }
+        if !steering.accept(&candidate) {
+            debug!("attempt {} not accepted -> {:?}", attempt, candidate.line);
+            continue;
+        }
+        return Some(candidate)
+    }
+    None
+}
+
+// generates up to best_of novel, accepted candidates and keeps the highest scoring
+pub fn generate_best<R : Rng>(model:&Model, rng: &mut R, sentence_start:&Vec<Token>, user_model:&UserGenerativeModel, config:&GenerationConfig, steering:&Steering) -> Option<Candidate> {
+    let mut best : Option<(f64, Candidate)> = None;
+    let steering = &Filtered { config: config, inner: steering };
+
+    for _ in 0..config.best_of.max(1) {
+        let candidate = match generate_novel(model, rng, sentence_start, user_model, config, steering) {
+            Some(candidate) => candidate,
+            None => continue,
+        };
+
+        let score = candidate.score(config);
+        debug!("candidate scored {:.3} -> {:?}", score, candidate.line);
+        let better = match best {
+            Some((best_score, _)) => score > best_score,
+            None => true,
+        };
+        if better {
+            best = Some((score, candidate));
+        }
+    }
+
+    best.map(|(_, mut candidate)| {
+        realize_numbers(model, rng, &mut candidate.line);
+        candidate
+    })
+}
+
+// an unknown word has to share at least this much of its ending to stand in by suffix
+const SUFFIX_MATCH_LEN : usize = 4;
+
+#[derive(Debug, Clone)]
+pub struct ResolvedStart {
+    // what the user actually wrote, for display
+    pub original: Line,
+    // what generation continues from
+    pub context: Line,
+    // (never seen, went with) for each word that had to be swapped
+    pub substitutions: Vec<(String, String)>,
+}
+
+impl ResolvedStart {
+    // the user's own words followed by whatever got generated after the context
+    pub fn splice(&self, candidate:&Candidate) -> Line {
+        let mut line = self.original.clone();
+        line.extend(candidate.line[self.context.len()..].iter().cloned());
+        line
+    }
+}
+
+// maps out of vocabulary words to a close known word (preferring ones this user says),
+// then backs off to the last token the model knows. a word with nothing close is an error,
+// carrying on from a random start instead would ignore what was asked for
+pub fn resolve_start(model:&Model, user_model:&UserGenerativeModel, sentence_start:&Line) -> Result<ResolvedStart, String> {
+    let mut context : Line = Vec::with_capacity(sentence_start.len());
+    let mut substitutions : Vec<(String, String)> = Vec::new();
+    let mut vocabulary : Option<(Vec<&str>, Vec<&str>)> = None;
+
+    for token in sentence_start {
+        let word = match token {
+            &Token::Word(ref word) if !model.token_to_idx.contains_key(token) => word,
+            _ => {
+                context.push(normalize_token(token));
+                continue;
+            },
+        };
+
+        let &mut (ref user_words, ref all_words) = vocabulary.get_or_insert_with(|| {
+            let mut user_words = Vec::new();
+            let mut all_words = Vec::new();
+            for (idx, t) in model.tokens.iter().enumerate() {
+                if let &Token::Word(ref w) = t {
+                    if user_model.own_bigrams.context_map.contains_key(&idx) {
+                        user_words.push(w.as_str());
+                    }
+                    all_words.push(w.as_str());
+                }
+            }
+            (user_words, all_words)
+        });
+
+        let replacement = nearest_by_edit(word, user_words.iter().cloned()).map(|(w, _)| w)
+            .or_else(|| nearest_by_edit(word, all_words.iter().cloned()).map(|(w, _)| w))
+            .or_else(|| nearest_by_suffix(word, user_words.iter().cloned(), SUFFIX_MATCH_LEN))
+            .or_else(|| nearest_by_suffix(word, all_words.iter().cloned(), SUFFIX_MATCH_LEN));
+
+        match replacement {
+            Some(known) => {
+                substitutions.push((word.clone(), String::from(known)));
+                context.push(Token::Word(String::from(known)));
+            },
+            None => return Err(format!("never heard {:?}, or anything like it", word)),
+        }
+    }
+
+    while context.len() > 1 && context.last().map(|t| !model.token_to_idx.contains_key(t)).unwrap_or(false) {
+        context.pop();
+    }
+
+    Ok(ResolvedStart {
+        original: sentence_start.clone(),
+        context: context,
+        substitutions: substitutions,
+    })
+}
+
+pub fn generate_steered<R : Rng>(model:&Model, rng: &mut R, sentence_start:&Vec<Token>, user_model:&UserGenerativeModel, steering:&Steering) -> Candidate {
     let to_idx = |t:&Token| -> TokenIdx {
         *model.token_to_idx.get(t).unwrap()
     };
@@ -44,13 +334,17 @@ pub fn generate<R : Rng>(model:&Model, rng: &mut R, sentence_start:&Vec<Token>,
         popular: Vec::new(),
     };
 
-    println!("starting line -> {:?}", line);
-    
-    while line.last() != Some(&Token::End) && line.len() < 30 {
+    debug!("starting line -> {:?}", line);
+
+    let mut steps : Vec<Json> = Vec::new();
+    let mut log_likelihood = 0.0;
+    let mut branching = 0;
+
+    while line.last() != Some(&Token::End) && line.len() < MAX_LINE_LENGTH {
         let mut selections : Vec<(Option<GeneratedToken>, f64)> = Vec::new();
 
-        let trigram_selection = user_model.own_trigrams.generate(&line, line.len(), &model.token_to_idx, rng);
-        let bigram_selection = user_model.own_bigrams.generate(&line, line.len(), &model.token_to_idx, rng);
+        let trigram_selection = user_model.own_trigrams.generate(&line, line.len(), model, steering, rng);
+        let bigram_selection = user_model.own_bigrams.generate(&line, line.len(), model, steering, rng);
         
         selections.push((trigram_selection.clone(),0.74));
       
@@ -59,12 +353,12 @@ pub fn generate<R : Rng>(model:&Model, rng: &mut R, sentence_start:&Vec<Token>,
                 &Some(ref generated_token) => {
                     let token = to_token(generated_token.token_idx);
                     if generated_token.table_occurrences <= 1 {
-                        println!("=== rejected table ==== token \"{:?}\" with p {:2} ", token, probability);
+                        trace!("rejected table, token \"{:?}\" with p {:2}", token, probability);
                         false
                     }  else {
                         let roll = rng.next_f64();
                         let take = roll <= probability;
-                        println!("=== rolling for === token \"{:?}\" with p {:2} roll {:2} take? {:}", token, probability, roll, take);
+                        trace!("rolling for token \"{:?}\" with p {:2} roll {:2} take? {:}", token, probability, roll, take);
                         take
                     } 
                 }
@@ -72,23 +366,64 @@ pub fn generate<R : Rng>(model:&Model, rng: &mut R, sentence_start:&Vec<Token>,
             }
         }).next().and_then(|(x,_)| x); // .clone()
 
+        let source = if primary_selection.is_some() {
+            "trigram"
+        } else if bigram_selection.is_some() {
+            "bigram"
+        } else {
+            "last_resort"
+        };
+
         let generated_token = primary_selection.or(bigram_selection.clone()).unwrap_or_else(|| last_resort.clone()); // last resort is naive bigram
         let token = to_token(generated_token.token_idx);
 
-        println!("{:20} tri {:120} bi {:120}", 
+        log_likelihood += if generated_token.table_occurrences > 0 {
+            (generated_token.chosen_occurrences as f64 / generated_token.table_occurrences as f64).ln()
+        } else {
+            LAST_RESORT_LOG_P
+        };
+        if generated_token.popular.len() > 1 {
+            branching += 1;
+        }
+
+        trace!("{:20} tri {:120} bi {:120}", 
             format_token(&token),
             format_selection(&trigram_selection, &model.tokens), 
             format_selection(&bigram_selection, &model.tokens),
         );
 
+        if trace::tracing() {
+            let mut step = BTreeMap::new();
+            step.insert(String::from("token"), format_token(&token).to_json());
+            step.insert(String::from("source"), source.to_json());
+            step.insert(String::from("trigram"), selection_json(&trigram_selection, &model.tokens));
+            step.insert(String::from("bigram"), selection_json(&bigram_selection, &model.tokens));
+            steps.push(Json::Object(step));
+        }
+
         line.push(token);
     }    
 
-    generate_sentence(&line)
+    if trace::tracing() {
+        let mut record = BTreeMap::new();
+        record.insert(String::from("start"), sentence_start.iter().map(format_token).collect::<Vec<_>>().to_json());
+        record.insert(String::from("steps"), Json::Array(steps));
+        record.insert(String::from("sentence"), generate_sentence(&line).to_json());
+        record.insert(String::from("log_likelihood"), log_likelihood.to_json());
+        trace::record("generation", Json::Object(record));
+    }
+
+    Candidate {
+        line: line,
+        start_len: sentence_start.len(),
+        log_likelihood: log_likelihood,
+        branching: branching,
+    }
 }
 
 pub fn choose_user<R : Rng>(model: &Model, rng: &mut R) -> UserId {
-    let user_ids : Vec<UserId> = model.users.keys().cloned().collect();
+    let mut user_ids : Vec<UserId> = model.users.keys().cloned().collect();
+    user_ids.sort(); // stable order so a seed always picks the same user
     let user_idx = rng.gen_range(0, user_ids.len());
     user_ids[user_idx]
 } 
@@ -121,6 +456,23 @@ pub fn format_selection(selection: &Option<GeneratedToken>, all_tokens:&Vec<Toke
     }
 }
 
+pub fn selection_json(selection: &Option<GeneratedToken>, all_tokens:&Vec<Token>) -> Json {
+    if let &Some(ref generated_token) = selection {
+        let mut object = BTreeMap::new();
+        object.insert(String::from("token"), format_token(&all_tokens[generated_token.token_idx]).to_json());
+        object.insert(String::from("chosen"), generated_token.chosen_occurrences.to_json());
+        object.insert(String::from("table"), generated_token.table_occurrences.to_json());
+
+        let populars : Vec<Json> = generated_token.popular.iter().map(|&(token_idx, count)| {
+            vec!(format_token(&all_tokens[token_idx]).to_json(), count.to_json()).to_json()
+        }).collect();
+        object.insert(String::from("popular"), Json::Array(populars));
+
+        Json::Object(object)
+    } else {
+        Json::Null
+    }
+}
 
 #[derive(Clone)]
 pub struct GeneratedToken {
@@ -132,17 +484,18 @@ pub struct GeneratedToken {
 }
 
 impl<C> GenerativeModel<C> where C: Eq + Hash + Copy {
-    pub fn generate<R : Rng>(&self, current:&Line, idx:usize, token_map:&HashMap<Token, usize>, rng: &mut R) -> Option<GeneratedToken> {
+    pub fn generate<R : Rng>(&self, current:&Line, idx:usize, model:&Model, steering:&Steering, rng: &mut R) -> Option<GeneratedToken> {
         let cp = self.context_production;
-        cp(current, idx, token_map).and_then(|context| {
-            self.context_map.get(&context).map(|table| {
-                let (token_idx, token_count) = select_from(table, rng);
-                GeneratedToken {
-                    token_idx: token_idx,
-                    chosen_occurrences: token_count,
-                    table_occurrences: table.occurences,
-                    popular: most_popular(table, 3),
-                }
+        cp(current, idx, &model.token_to_idx).and_then(|context| {
+            self.context_map.get(&context).and_then(|table| {
+                select_weighted(table, rng, |token_idx| steering.weight(model, current, token_idx)).map(|(token_idx, token_count)| {
+                    GeneratedToken {
+                        token_idx: token_idx,
+                        chosen_occurrences: token_count,
+                        table_occurrences: table.occurences,
+                        popular: most_popular(table, 3),
+                    }
+                })
             })
         })
     }
@@ -162,6 +515,35 @@ pub fn select_from<R : Rng>(table:&CumulativeWordTable, rng: &mut R) -> (TokenId
     (0, 0)
 }
 
+// select_from with each token's count scaled by weight, None if everything was weighted out
+pub fn select_weighted<R : Rng, F>(table:&CumulativeWordTable, rng: &mut R, weight: F) -> Option<(TokenIdx, usize)> where F : Fn(TokenIdx) -> f64 {
+    let weights : Vec<f64> = table.token_table.iter().map(|&(token_idx, _)| weight(token_idx)).collect();
+
+    if weights.iter().all(|&w| w == 1.0) {
+        return Some(select_from(table, rng))
+    }
+
+    let mut weighted : Vec<(TokenIdx, usize, f64)> = Vec::with_capacity(table.token_table.len());
+    let mut last_occur : OccurenceCount = 0;
+    let mut total = 0.0;
+
+    for (&(token_idx, occur), w) in table.token_table.iter().zip(weights) {
+        let this_occur = occur - last_occur;
+        last_occur = occur;
+        if w > 0.0 {
+            total += (this_occur as f64) * w;
+            weighted.push((token_idx, this_occur, total));
+        }
+    }
+
+    if total <= 0.0 {
+        return None
+    }
+
+    let n = rng.next_f64() * total;
+    weighted.iter().find(|&&(_, _, cumulative)| n < cumulative).or(weighted.last()).map(|&(token_idx, count, _)| (token_idx, count))
+}
+
 pub fn most_popular(table:&CumulativeWordTable, n:usize) -> Vec<(TokenIdx, usize)> {
     let mut out:Vec<(TokenIdx, usize)> = Vec::new();
     let mut last_occur : OccurenceCount = 0;
@@ -175,38 +557,120 @@ pub fn most_popular(table:&CumulativeWordTable, n:usize) -> Vec<(TokenIdx, usize
     out
 }
 
+fn capitalize(word:&str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn ideographic(word:&str) -> bool {
+    word.chars().next().map(is_ideograph).unwrap_or(false)
+}
+
+// "i", "i'm", "i'll" are always capitalized
+fn is_first_person(word:&str) -> bool {
+    word == "i" || word.starts_with("i'")
+}
+
 pub fn generate_sentence(tokens:&Vec<Token>) -> String {
     use super::tokenizer::Token::*;
+    use super::tokenizer::PunctuationClass::*;
 
     let mut message = String::new();
 
-    let trail = tokens.iter();
-    let next = tokens.iter().skip(1);
-
-    for (t, n) in trail.zip(next) {
-        match (t, n) {
-            (&Start, _) => (),
-            (&End, _) => (),
-            (&Word(ref word), &Word(_)) | (&Word(ref word), &Link(_)) => { // words need a space betwene them
-                message.push_str(word);
-                message.push(' ');
-                ()
+    // whether the last thing written is happy to have a space after it
+    let mut space_after = false;
+    let mut sentence_start = true;
+    let mut open_quotes : Vec<char> = Vec::new();
+    let mut after_ideograph = false;
+    let mut after_emoji = false;
+
+    for token in tokens {
+        let mut ideograph = false;
+        let mut emoji_run = false;
+        match token {
+            &Start | &End => (),
+            &Newline => {
+                message.push('\n');
+                space_after = false;
+                sentence_start = true;
             },
-            (&Word(ref word), _) => message.push_str(word),
-            (&Link(ref link), &Link(_)) | (&Link(ref link), &Word(_)) => {
-                message.push_str(link);
-                message.push(' ');
-                ()
-            }
-            (&Link(ref link), _) => message.push_str(link),
-            (&Punctuation(ref punc, whitespace), _) => {
-                message.push_str(punc);
-                if whitespace {
+            &Word(ref word) => {
+                ideograph = ideographic(word);
+                if space_after && !(ideograph && after_ideograph) {
+                    message.push(' ');
+                }
+                if sentence_start || is_first_person(word) {
+                    message.push_str(&capitalize(word));
+                } else {
+                    message.push_str(word);
+                }
+                space_after = true;
+                sentence_start = false;
+            },
+            &Link(_, _) | &Number(_, _) | &Mention(_) | &Hashtag(_) | &Command(_) => {
+                if space_after {
+                    message.push(' ');
+                }
+                message.push_str(&token.to_string());
+                space_after = true;
+                sentence_start = false;
+            },
+            &Emoji(ref emoji) => {
+                // spaced like a word, but runs of emoji stay together
+                if space_after && !after_emoji {
+                    message.push(' ');
+                }
+                message.push_str(emoji);
+                space_after = true;
+                emoji_run = true;
+            },
+            &Punctuation(ref punc, whitespace) => {
+                let chars : Vec<char> = punc.chars().collect();
+                let (first, last) = match (chars.first(), chars.last()) {
+                    (Some(&f), Some(&l)) => (f, l),
+                    _ => continue,
+                };
+
+                let space_before = match punctuation_class(first) {
+                    Opening => space_after,
+                    Closing => false,
+                    Joining => space_after && whitespace,
+                    Quote => space_after && !open_quotes.contains(&first),
+                };
+
+                let mut last_opened = false;
+                for &c in &chars {
+                    if punctuation_class(c) == Quote {
+                        if let Some(at) = open_quotes.iter().position(|&q| q == c) {
+                            open_quotes.remove(at);
+                            last_opened = false;
+                        } else {
+                            open_quotes.push(c);
+                            last_opened = true;
+                        }
+                    }
+                }
+
+                if space_before {
                     message.push(' ');
                 }
-                ()
+                message.push_str(punc);
+
+                space_after = match punctuation_class(last) {
+                    Opening => false,
+                    Quote if last_opened => false,
+                    _ => whitespace,
+                };
+                if whitespace && ends_sentence(punc) {
+                    sentence_start = true;
+                }
             },
         }
+        after_ideograph = ideograph;
+        after_emoji = emoji_run;
     }
 
     message
@@ -0,0 +1,109 @@
+use std::fs::*;
+use std::io;
+use std::io::{BufRead, BufReader};
+use std::time::{Duration, Instant};
+
+use regex::{Regex, RegexBuilder};
+use chrono::NaiveDate;
+
+use super::HashSet;
+use super::model::UserId;
+use super::index::SearchIndex;
+use super::persistence::parse_log_line;
+
+// compiled patterns past this are refused rather than built
+const PATTERN_SIZE_LIMIT : usize = 1 << 20;
+
+#[derive(Debug, Clone)]
+pub struct GrepLimits {
+    pub max_matches: usize,
+    // log bytes read before giving up
+    pub max_bytes: u64,
+    pub max_time: Duration,
+}
+
+impl Default for GrepLimits {
+    fn default() -> GrepLimits {
+        GrepLimits {
+            max_matches: 10,
+            max_bytes: 64 * 1024 * 1024,
+            max_time: Duration::from_secs(2),
+        }
+    }
+}
+
+pub struct GrepMatch {
+    pub user_id: UserId,
+    pub text: String,
+    // byte ranges of text that matched
+    pub spans: Vec<(usize, usize)>,
+    pub date: Option<NaiveDate>,
+}
+
+pub struct GrepOutcome {
+    pub matches: Vec<GrepMatch>,
+    // why it stopped before reading everything, if it did
+    pub cut_short: Option<&'static str>,
+}
+
+// case insensitive unless the pattern says (?-i)
+pub fn compile_pattern(pattern:&str) -> Result<Regex, String> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .size_limit(PATTERN_SIZE_LIMIT)
+        .dfa_size_limit(PATTERN_SIZE_LIMIT)
+        .build()
+        .map_err(|e| format!("bad pattern: {}", e))
+}
+
+// the group's logs (and the imported history), newest file first, newest match first within each
+pub fn grep(index:&SearchIndex, regex:&Regex, users:&HashSet<UserId>, group_id:u64, limits:&GrepLimits) -> io::Result<GrepOutcome> {
+    let started = Instant::now();
+    let mut read : u64 = 0;
+    let mut outcome = GrepOutcome { matches: Vec::new(), cut_short: None };
+
+    let mut files : Vec<_> = index.files.iter().filter(|f| f.group.map(|g| g == group_id).unwrap_or(true)).collect();
+    files.sort_by(|a, b| b.date.cmp(&a.date));
+
+    'files: for file in files {
+        let reader = BufReader::new(try!(File::open(&file.path)));
+        let mut in_file : Vec<GrepMatch> = Vec::new();
+
+        for line_result in reader.lines() {
+            let line = try!(line_result);
+            read += line.len() as u64 + 1;
+            if read > limits.max_bytes {
+                outcome.cut_short = Some("read as much as it's allowed to");
+            } else if started.elapsed() > limits.max_time {
+                outcome.cut_short = Some("ran out of time");
+            }
+            if outcome.cut_short.is_some() {
+                break
+            }
+
+            if let Some(entry) = parse_log_line(&line) {
+                if !users.contains(&entry.user_id) {
+                    continue
+                }
+                let spans : Vec<(usize, usize)> = regex.find_iter(&entry.text).map(|m| (m.start(), m.end())).filter(|&(start, end)| start < end).collect();
+                if !spans.is_empty() {
+                    let date = entry.date().or(file.date);
+                    in_file.push(GrepMatch { user_id: entry.user_id, text: entry.text, spans: spans, date: date });
+                }
+            }
+        }
+
+        in_file.reverse();
+        for found in in_file {
+            if outcome.matches.len() >= limits.max_matches {
+                break 'files
+            }
+            outcome.matches.push(found);
+        }
+        if outcome.cut_short.is_some() {
+            break
+        }
+    }
+
+    Ok(outcome)
+}
@@ -8,11 +8,7 @@ use std::io::BufRead;
 use std::io;
 use std::io::Write;
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct TelegramMessage {
-    user_id:u64,
-    message:String,
-}
+use super::persistence::LogEntry;
 
 pub fn read_history(root_path:&Path, out:&Path) -> io::Result<()> {
     let mut file_out =  try!(OpenOptions::new().write(true).create(true).open(out));
@@ -21,8 +17,7 @@ pub fn read_history(root_path:&Path, out:&Path) -> io::Result<()> {
     let paths = path_result.map(|dir_entry| dir_entry.unwrap().path());
 
     for path in paths {
-        println!("about to read path {:?}", path);
-        println!("extension is {:?}", path.extension());
+        debug!("about to read path {:?} extension {:?}", path, path.extension());
         
         let extension_ok = path.extension().map(|e| e == "jsonl").unwrap_or(false);
         
@@ -31,8 +26,7 @@ pub fn read_history(root_path:&Path, out:&Path) -> io::Result<()> {
             let reader = BufReader::new(file);
             let messages = reader.lines().filter_map(|l| message_for_line(&l.unwrap()) );
             for message in messages {
-                let line = format!("{} {}\n", message.user_id, message.message);
-                try!(file_out.write_all(line.as_bytes()));
+                try!(file_out.write_all(message.to_log_line().as_bytes()));
             }
         }
     }
@@ -43,7 +37,12 @@ pub fn read_history(root_path:&Path, out:&Path) -> io::Result<()> {
     Ok(())
 }
 
-pub fn message_for_line(str:&str) -> Option<TelegramMessage> {
+// the export's ids are numbers in older dumps
+fn as_id(json:&Json) -> Option<i64> {
+    json.as_i64().or_else(|| json.as_string().and_then(|s| s.parse().ok()))
+}
+
+pub fn message_for_line(str:&str) -> Option<LogEntry> {
     let json = Json::from_str(str).expect("json of some kind");
     let event_type = json.find("event").unwrap().as_string().expect("event type");
 
@@ -55,10 +54,18 @@ pub fn message_for_line(str:&str) -> Option<TelegramMessage> {
         let message : Option<&str> = json.find("text").and_then(|x| x.as_string() );
 
         match (from_user_id, message) {
-            (Some(um), Some(m)) => Some(TelegramMessage { user_id: um, message: super::persistence::clean_message(m) }),
+            (Some(um), Some(m)) => Some(LogEntry {
+                user_id: um,
+                text: String::from(m),
+                timestamp: json.find("date").and_then(|d| d.as_i64()),
+                message_id: json.find("id").and_then(as_id),
+                reply_to: json.find("reply_id").and_then(as_id),
+                username: json.find_path(&["from", "username"]).and_then(|u| u.as_string()).map(String::from),
+                forward_from: json.find_path(&["fwd_from", "peer_id"]).and_then(|f| f.as_u64()),
+            }),
             _ => None,
         }      
     } else {
         None
     }
-}
\ No newline at end of file
+}
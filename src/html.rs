@@ -0,0 +1,229 @@
+use std::fmt;
+
+use super::tokenizer::continues_word;
+
+// telegram refuses anything longer
+pub const MAX_MESSAGE_LENGTH : usize = 4096;
+
+// no piece is longer than this once escaped, so a split never has to cut through markup
+const MAX_PIECE_LENGTH : usize = 512;
+
+fn escape_char(c:char) -> Option<&'static str> {
+    match c {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        '"' => Some("&quot;"),
+        _ => None,
+    }
+}
+
+pub fn escape_html(text:&str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match escape_char(c) {
+            Some(entity) => escaped.push_str(entity),
+            None => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// text escaped and cut into chunks of at most `limit` characters, after whitespace unless a word is too long to fit.
+// word_per_chunk cuts after every bit of whitespace, otherwise as many words as fit go together
+fn escaped_chunks(text:&str, limit:usize, word_per_chunk:bool) -> Vec<String> {
+    let mut chunks : Vec<String> = Vec::new();
+    let mut chunk = String::new();
+    let mut length = 0;
+    // (byte, char) position just after the last whitespace in the chunk
+    let mut last_break : Option<(usize, usize)> = None;
+
+    for c in text.chars() {
+        let mut buffer = [0; 4];
+        let escaped : &str = match escape_char(c) {
+            Some(entity) => entity,
+            None => c.encode_utf8(&mut buffer),
+        };
+        let escaped_length = escaped.chars().count();
+
+        while length > 0 && length + escaped_length > limit {
+            match last_break.take() {
+                Some((at, chars)) => {
+                    let rest = chunk.split_off(at);
+                    chunks.push(chunk);
+                    chunk = rest;
+                    length -= chars;
+                },
+                None => {
+                    chunks.push(chunk);
+                    chunk = String::new();
+                    length = 0;
+                },
+            }
+        }
+
+        chunk.push_str(escaped);
+        length += escaped_length;
+        if c.is_whitespace() {
+            if word_per_chunk {
+                chunks.push(chunk);
+                chunk = String::new();
+                length = 0;
+            } else {
+                last_break = Some((chunk.len(), length));
+            }
+        }
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+// a reply for ParseMode::Html, everything that goes in is escaped.
+// it's kept as pieces that are each safe to split after, a word of text or a whole tag
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Html {
+    pieces: Vec<String>,
+}
+
+impl Html {
+    pub fn new() -> Html {
+        Html::default()
+    }
+
+    pub fn plain(text:&str) -> Html {
+        let mut html = Html::new();
+        html.text(text);
+        html
+    }
+
+    // one piece per word, with the whitespace after it
+    pub fn text(&mut self, text:&str) -> &mut Html {
+        self.pieces.extend(escaped_chunks(text, MAX_PIECE_LENGTH, true));
+        self
+    }
+
+    // text too long for one piece goes in several, each wrapped in its own tags
+    fn tagged(&mut self, tag:&str, text:&str) -> &mut Html {
+        let tags_length = 2 * tag.len() + 5;
+        for chunk in escaped_chunks(text, MAX_PIECE_LENGTH - tags_length, false) {
+            self.pieces.push(format!("<{}>{}</{}>", tag, chunk, tag));
+        }
+        self
+    }
+
+    pub fn bold(&mut self, text:&str) -> &mut Html {
+        self.tagged("b", text)
+    }
+
+    pub fn italic(&mut self, text:&str) -> &mut Html {
+        self.tagged("i", text)
+    }
+
+    pub fn code(&mut self, text:&str) -> &mut Html {
+        self.tagged("code", text)
+    }
+
+    // a link too long to be one piece is left as its text
+    pub fn link(&mut self, text:&str, url:&str) -> &mut Html {
+        let link = format!("<a href=\"{}\">{}</a>", escape_html(url), escape_html(text));
+        if link.chars().count() > MAX_PIECE_LENGTH {
+            return self.text(text)
+        }
+        self.pieces.push(link);
+        self
+    }
+
+    pub fn line(&mut self) -> &mut Html {
+        self.pieces.push(String::from("\n"));
+        self
+    }
+
+    pub fn append(&mut self, other:Html) -> &mut Html {
+        self.pieces.extend(other.pieces);
+        self
+    }
+
+    // text with whole word (case insensitive) matches of any of the terms in bold
+    pub fn highlighted(&mut self, text:&str, terms:&[String]) -> &mut Html {
+        let chars : Vec<char> = text.chars().collect();
+        let lower : Vec<char> = chars.iter().map(|c| c.to_lowercase().next().unwrap_or(*c)).collect();
+        let is_boundary = |at:usize| at == 0 || at >= chars.len() || !continues_word(chars[at - 1]) || !continues_word(chars[at]);
+
+        let mut matches : Vec<(usize, usize)> = Vec::new();
+        for term in terms {
+            let term : Vec<char> = term.chars().collect();
+            if term.is_empty() || term.len() > lower.len() {
+                continue
+            }
+            for start in 0..(lower.len() - term.len() + 1) {
+                let end = start + term.len();
+                if lower[start..end] == term[..] && is_boundary(start) && is_boundary(end) {
+                    matches.push((start, end));
+                }
+            }
+        }
+        // char positions to byte offsets
+        let offsets : Vec<usize> = text.char_indices().map(|(at, _)| at).chain(Some(text.len())).collect();
+        let spans : Vec<(usize, usize)> = matches.into_iter().map(|(start, end)| (offsets[start], offsets[end])).collect();
+        self.marked(text, &spans)
+    }
+
+    // text with the given byte ranges in bold, earliest first, longest first when they start together, overlaps dropped
+    pub fn marked(&mut self, text:&str, spans:&[(usize, usize)]) -> &mut Html {
+        let mut spans : Vec<(usize, usize)> = spans.iter().cloned().filter(|&(start, end)| start < end && end <= text.len()).collect();
+        spans.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+
+        let mut at = 0;
+        for (start, end) in spans {
+            if start < at {
+                continue
+            }
+            self.text(&text[at..start]);
+            self.bold(&text[start..end]);
+            at = end;
+        }
+        self.text(&text[at..])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pieces.iter().all(|p| p.trim().is_empty())
+    }
+
+    // messages of at most `limit` characters (if that's no shorter than a piece), broken at a line if there's one in the back half
+    pub fn split(&self, limit:usize) -> Vec<String> {
+        let mut messages : Vec<String> = Vec::new();
+        let mut current : Vec<&str> = Vec::new();
+        let mut length = 0;
+
+        for piece in &self.pieces {
+            let piece_length = piece.chars().count();
+            while length + piece_length > limit && !current.is_empty() {
+                let last_line = current.iter().rposition(|p| p.ends_with('\n'));
+                let break_after = match last_line {
+                    Some(at) if current[..at + 1].iter().map(|p| p.chars().count()).sum::<usize>() > limit / 2 => at + 1,
+                    _ => current.len(),
+                };
+                let rest = current.split_off(break_after);
+                messages.push(current.concat());
+                current = rest;
+                length = current.iter().map(|p| p.chars().count()).sum();
+            }
+            current.push(piece);
+            length += piece_length;
+        }
+        if !current.is_empty() {
+            messages.push(current.concat());
+        }
+
+        // only the line breaks between parts go, a part can start with an indented line
+        messages.into_iter().filter(|m| !m.trim().is_empty()).map(|m| String::from(m.trim_matches('\n'))).collect()
+    }
+}
+
+impl fmt::Display for Html {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.pieces.concat())
+    }
+}
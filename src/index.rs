@@ -0,0 +1,595 @@
+use std::fs::*;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use super::{HashMap, HashSet};
+use super::model::UserId;
+use super::persistence::{LogEntry, parse_log_line};
+use super::search::{SearchResult, SearchConfig, SortOrder, Query, Phrase, search_terms, date_for_log};
+use super::stem::stem;
+use super::fuzzy::within_typo_distance;
+
+use chrono::{Local, NaiveDate};
+use std::cmp::Ordering;
+use std::time::{Duration, Instant};
+
+pub type FileId = u32;
+pub type DocId = u32;
+pub type TermFrequency = u32;
+
+pub const INDEX_FILE : &'static str = "search.idx";
+const INDEX_HEADER : &'static str = "robbot-index 2";
+const DATE_FORMAT : &'static str = "%Y-%m-%d";
+
+// while the bot runs the index is rewritten at most this often, and again when it stops listening.
+// anything newer is caught up from the logs at startup
+const SAVE_INTERVAL : u64 = 15 * 60;
+
+#[derive(Debug, Clone)]
+pub struct LogFile {
+    pub path: PathBuf,
+    // None for the imported history.log, which sits outside any group directory
+    pub group: Option<u64>,
+    // from the file name, history.log has none
+    pub date: Option<NaiveDate>,
+    // how far into the file has been indexed
+    pub indexed_len: u64,
+}
+
+// one message, found again by seeking to its offset
+#[derive(Debug, Clone, Copy)]
+pub struct Doc {
+    pub file: FileId,
+    pub offset: u64,
+    pub user_id: UserId,
+    pub length: u32,
+    // when it was sent, if the log says
+    pub date: Option<NaiveDate>,
+}
+
+// term -> messages containing it, so /search doesn't have to read every log
+pub struct SearchIndex {
+    pub path: PathBuf,
+    pub files: Vec<LogFile>,
+    pub file_ids: HashMap<PathBuf, FileId>,
+    pub docs: Vec<Doc>,
+    // each file's docs in order, for the messages around a result (not saved, rebuilt on load)
+    pub file_docs: Vec<Vec<DocId>>,
+    // doc ids in each posting list are ascending, docs are only ever appended
+    pub postings: HashMap<String, Vec<(DocId, TermFrequency)>>,
+    // stem -> the indexed terms with that stem (not saved, rebuilt on load)
+    pub stems: HashMap<String, Vec<String>>,
+    pub total_length: u64,
+    unsaved: usize,
+    last_saved: Instant,
+}
+
+fn date_for_path(path:&Path) -> Option<NaiveDate> {
+    path.file_name().and_then(|n| n.to_str()).and_then(|n| date_for_log(n))
+}
+
+pub fn group_for_path(path:&Path) -> Option<u64> {
+    path.parent().and_then(|p| p.file_name()).and_then(|n| n.to_str()).and_then(|n| n.parse().ok())
+}
+
+fn corrupt(what:&str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("bad search index, {}", what))
+}
+
+impl SearchIndex {
+    pub fn empty(path:&Path) -> SearchIndex {
+        SearchIndex {
+            path: PathBuf::from(path),
+            files: Vec::new(),
+            file_ids: HashMap::default(),
+            docs: Vec::new(),
+            file_docs: Vec::new(),
+            postings: HashMap::default(),
+            stems: HashMap::default(),
+            total_length: 0,
+            unsaved: 0,
+            last_saved: Instant::now(),
+        }
+    }
+
+    // loads the saved index next to the logs (if any) and indexes whatever was logged since
+    pub fn open(root:&Path, log_paths:&Vec<PathBuf>) -> io::Result<SearchIndex> {
+        let path = root.join(INDEX_FILE);
+        let mut index = if path.is_file() {
+            match SearchIndex::load(&path) {
+                Ok(index) => index,
+                Err(e) => {
+                    warn!("couldn't load search index {:?}, rebuilding -> {:?}", path, e);
+                    SearchIndex::empty(&path)
+                },
+            }
+        } else {
+            SearchIndex::empty(&path)
+        };
+
+        // a log that got shorter was rewritten, offsets into it can't be trusted
+        let shrunk = index.files.iter().any(|f| metadata(&f.path).map(|m| m.len() < f.indexed_len).unwrap_or(false));
+        if shrunk {
+            info!("a log file shrank, rebuilding search index");
+            index = SearchIndex::empty(&path);
+        }
+
+        let before = index.docs.len();
+        for log_path in log_paths {
+            try!(index.catch_up(log_path));
+        }
+        info!("search index has {} messages, {} new", index.docs.len(), index.docs.len() - before);
+
+        if index.docs.len() != before || !path.is_file() {
+            try!(index.save());
+        }
+
+        Ok(index)
+    }
+
+    fn file_id(&mut self, path:&Path) -> FileId {
+        if let Some(&id) = self.file_ids.get(path) {
+            return id
+        }
+        let id = self.files.len() as FileId;
+        self.files.push(LogFile { path: PathBuf::from(path), group: group_for_path(path), date: date_for_path(path), indexed_len: 0 });
+        self.file_docs.push(Vec::new());
+        self.file_ids.insert(PathBuf::from(path), id);
+        id
+    }
+
+    // indexes anything past the point this file was last indexed to
+    pub fn catch_up(&mut self, path:&Path) -> io::Result<()> {
+        let file_id = self.file_id(path);
+        let from = self.files[file_id as usize].indexed_len;
+
+        let mut file = try!(File::open(path));
+        if try!(file.metadata()).len() <= from {
+            return Ok(())
+        }
+        try!(file.seek(SeekFrom::Start(from)));
+
+        let mut reader = BufReader::new(file);
+        let mut offset = from;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = try!(reader.read_line(&mut line));
+            if read == 0 || !line.ends_with('\n') { // a half written line gets picked up next time
+                break;
+            }
+            if let Some(entry) = parse_log_line(&line) {
+                self.add_doc(file_id, offset, &entry);
+            }
+            offset += read as u64;
+        }
+        self.files[file_id as usize].indexed_len = offset;
+
+        Ok(())
+    }
+
+    fn add_doc(&mut self, file:FileId, offset:u64, entry:&LogEntry) -> DocId {
+        let doc_id = self.docs.len() as DocId;
+        let terms = search_terms(&entry.text);
+
+        let mut frequencies : HashMap<String, TermFrequency> = HashMap::default();
+        for term in &terms {
+            *frequencies.entry(term.clone()).or_insert(0) += 1;
+        }
+        for (term, tf) in frequencies {
+            if !self.postings.contains_key(&term) {
+                self.stems.entry(stem(&term)).or_insert_with(Vec::new).push(term.clone());
+            }
+            self.postings.entry(term).or_insert_with(Vec::new).push((doc_id, tf));
+        }
+
+        self.docs.push(Doc { file: file, offset: offset, user_id: entry.user_id, length: terms.len() as u32, date: entry.date() });
+        self.file_docs[file as usize].push(doc_id);
+        self.total_length += terms.len() as u64;
+        doc_id
+    }
+
+    // a message just appended to a log, only kept in memory until the next save
+    pub fn add_message(&mut self, path:&Path, offset:u64, line_len:u64, entry:&LogEntry) -> io::Result<()> {
+        let file_id = self.file_id(path);
+        if self.files[file_id as usize].indexed_len != offset {
+            try!(self.catch_up(path)); // missed something, read it from the log instead
+        } else {
+            self.add_doc(file_id, offset, entry);
+            self.files[file_id as usize].indexed_len = offset + line_len;
+        }
+
+        self.unsaved += 1;
+        Ok(())
+    }
+
+    pub fn has_unsaved(&self) -> bool {
+        self.unsaved > 0
+    }
+
+    // saves if there's anything new and the last save was long enough ago
+    pub fn save_if_due(&mut self) -> io::Result<()> {
+        if self.has_unsaved() && self.last_saved.elapsed() >= Duration::from_secs(SAVE_INTERVAL) {
+            try!(self.save());
+        }
+        Ok(())
+    }
+
+    // docs containing every term, in log order
+    pub fn lookup(&self, terms:&[String]) -> Vec<DocId> {
+        let mut lists : Vec<&Vec<(DocId, TermFrequency)>> = Vec::with_capacity(terms.len());
+        for term in terms {
+            match self.postings.get(term) {
+                Some(list) => lists.push(list),
+                None => return Vec::new(),
+            }
+        }
+        lists.sort_by_key(|l| l.len());
+
+        let mut docs : Vec<DocId> = match lists.first() {
+            Some(shortest) => shortest.iter().map(|&(doc, _)| doc).collect(),
+            None => return Vec::new(),
+        };
+        for list in lists.iter().skip(1) {
+            let in_list : HashSet<DocId> = list.iter().map(|&(doc, _)| doc).collect();
+            docs.retain(|doc| in_list.contains(doc));
+        }
+        docs
+    }
+
+    // the vocabulary a single query word stands for: itself, other forms of it and (for ~words) near misses
+    pub fn expansions(&self, term:&str, query:&Query, config:&SearchConfig) -> Vec<String> {
+        let mut words : Vec<String> = vec![String::from(term)];
+        let fuzzy = query.fuzzy.contains(term);
+
+        if fuzzy {
+            for close in within_typo_distance(term, self.postings.keys().map(|k| k.as_str())) {
+                words.push(String::from(close));
+            }
+        }
+        if fuzzy || (config.stemming && !query.exact.contains(term)) {
+            if let Some(forms) = self.stems.get(&stem(term)) {
+                words.extend(forms.iter().cloned());
+            }
+        }
+
+        let mut seen : HashSet<String> = HashSet::default();
+        words.retain(|w| seen.insert(w.clone()));
+        words.truncate(config.max_expansions.max(1));
+        words
+    }
+
+    // swaps each single word for the vocabulary it expands to, before the query hits the postings
+    pub fn expand(&self, query:&mut Query, config:&SearchConfig) {
+        let expand_phrase = |phrase:&Phrase| -> Vec<Phrase> {
+            if phrase.len() == 1 {
+                self.expansions(&phrase[0], query, config).into_iter().map(|w| vec![w]).collect()
+            } else {
+                vec![phrase.clone()]
+            }
+        };
+
+        let required : Vec<Vec<Phrase>> = query.required.iter().map(|clause| clause.iter().flat_map(|p| expand_phrase(p)).collect()).collect();
+        let excluded : Vec<Phrase> = query.excluded.iter().flat_map(|p| expand_phrase(p)).collect();
+        query.required = required;
+        query.excluded = excluded;
+    }
+
+    // narrows down with the postings, then reads the messages back only if there are phrases to check
+    pub fn search(&self, query:&Query) -> Vec<DocId> {
+        let mut docs : Option<Vec<DocId>> = None; // None is everything
+        for clause in &query.required {
+            let mut either : Vec<DocId> = clause.iter().flat_map(|phrase| self.lookup(phrase)).collect();
+            either.sort();
+            either.dedup();
+            docs = Some(match docs {
+                Some(mut docs) => {
+                    let in_clause : HashSet<DocId> = either.into_iter().collect();
+                    docs.retain(|doc| in_clause.contains(doc));
+                    docs
+                },
+                None => either,
+            });
+        }
+        let mut docs = docs.unwrap_or_else(|| (0..self.docs.len() as DocId).collect());
+
+        docs.retain(|&doc_id| {
+            let doc = &self.docs[doc_id as usize];
+            query.users.contains(&doc.user_id) && query.in_dates(self.date(doc_id))
+        });
+
+        for phrase in query.excluded.iter().filter(|phrase| phrase.len() == 1) {
+            if let Some(list) = self.postings.get(&phrase[0]) {
+                let excluded : HashSet<DocId> = list.iter().map(|&(doc, _)| doc).collect();
+                docs.retain(|doc| !excluded.contains(doc));
+            }
+        }
+
+        if query.has_phrases() {
+            let unsettled = self.unsettled(&docs, query);
+            let entries = match self.messages(&unsettled) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    warn!("couldn't read search results -> {:?}", e);
+                    HashMap::default()
+                },
+            };
+            let unsettled : HashSet<DocId> = unsettled.into_iter().collect();
+            docs.retain(|doc| !unsettled.contains(doc) || entries.get(doc).map(|entry| query.matches(&search_terms(&entry.text))).unwrap_or(false));
+        }
+
+        docs
+    }
+
+    // the docs a phrase could go either way for and so have to be read, the rest are settled by the postings.
+    // a clause is already met by any of its single words, and a message missing one of a phrase's words can't contain it
+    fn unsettled(&self, docs:&[DocId], query:&Query) -> Vec<DocId> {
+        let mut unsettled : HashSet<DocId> = HashSet::default();
+        for clause in query.required.iter().filter(|clause| clause.iter().any(|phrase| phrase.len() > 1)) {
+            let by_words : HashSet<DocId> = clause.iter().filter(|phrase| phrase.len() == 1).flat_map(|phrase| self.lookup(phrase)).collect();
+            unsettled.extend(docs.iter().filter(|doc| !by_words.contains(doc)));
+        }
+        for phrase in query.excluded.iter().filter(|phrase| phrase.len() > 1) {
+            let with_words : HashSet<DocId> = self.lookup(phrase).into_iter().collect();
+            unsettled.extend(docs.iter().filter(|doc| with_words.contains(doc)));
+        }
+        docs.iter().cloned().filter(|doc| unsettled.contains(doc)).collect()
+    }
+
+    // when the message was sent, or failing that the day its log is named for
+    pub fn date(&self, doc_id:DocId) -> Option<NaiveDate> {
+        let doc = &self.docs[doc_id as usize];
+        doc.date.or(self.files[doc.file as usize].date)
+    }
+
+    // BM25 over the query's terms, each counted once however many phrases it's in
+    pub fn relevance(&self, doc_id:DocId, terms:&[String], config:&SearchConfig) -> f64 {
+        let doc_count = self.docs.len() as f64;
+        let average_length = if self.docs.is_empty() { 1.0 } else { (self.total_length as f64 / doc_count).max(1.0) };
+        let length = self.docs[doc_id as usize].length as f64;
+
+        terms.iter().map(|term| {
+            let list = match self.postings.get(term) {
+                Some(list) => list,
+                None => return 0.0,
+            };
+            let tf = match list.binary_search_by_key(&doc_id, |&(doc, _)| doc) {
+                Ok(at) => list[at].1 as f64,
+                Err(_) => return 0.0,
+            };
+            let containing = list.len() as f64;
+            let idf = ((doc_count - containing + 0.5) / (containing + 0.5) + 1.0).ln();
+            idf * tf * (config.k1 + 1.0) / (tf + config.k1 * (1.0 - config.b + config.b * length / average_length))
+        }).sum()
+    }
+
+    // 1 for an undated message, up to 1 + recency_weight for one from today
+    pub fn recency(&self, doc_id:DocId, today:NaiveDate, config:&SearchConfig) -> f64 {
+        match self.date(doc_id) {
+            Some(date) => {
+                let age = today.signed_duration_since(date).num_days().max(0) as f64;
+                1.0 + config.recency_weight * (0.5f64).powf(age / config.recency_half_life)
+            },
+            None => 1.0,
+        }
+    }
+
+    // undated messages (old imports) count as older than everything else, ties keep log order
+    fn chronological(&self, a:DocId, b:DocId) -> Ordering {
+        self.date(a).cmp(&self.date(b)).then(a.cmp(&b))
+    }
+
+    pub fn rank(&self, docs:&mut Vec<DocId>, query:&Query, config:&SearchConfig) {
+        match query.sort {
+            SortOrder::Oldest => docs.sort_by(|&a, &b| self.chronological(a, b)),
+            SortOrder::Newest => docs.sort_by(|&a, &b| self.chronological(b, a)),
+            SortOrder::Relevance => {
+                let terms = query.terms();
+                let today = Local::today().naive_local();
+                let mut scored : Vec<(f64, DocId)> = docs.iter().map(|&doc| {
+                    (self.relevance(doc, &terms, config) * self.recency(doc, today, config), doc)
+                }).collect();
+                // ties, and queries that are only filters, go newest first
+                scored.sort_by(|&(score_a, a), &(score_b, b)| {
+                    score_b.partial_cmp(&score_a).unwrap_or(Ordering::Equal).then(self.chronological(b, a))
+                });
+                *docs = scored.into_iter().map(|(_, doc)| doc).collect();
+            },
+        }
+    }
+
+    // reads a message back out of its log
+    pub fn message(&self, doc_id:DocId) -> io::Result<LogEntry> {
+        let doc = self.docs[doc_id as usize];
+        let mut file = try!(File::open(&self.files[doc.file as usize].path));
+        try!(file.seek(SeekFrom::Start(doc.offset)));
+
+        let mut line = String::new();
+        try!(BufReader::new(file).read_line(&mut line));
+
+        parse_log_line(&line).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidData, format!("no message at {:?} offset {}", self.files[doc.file as usize].path, doc.offset))
+        })
+    }
+
+    // reads a batch of messages back, opening each log once and reading forward from its first one
+    pub fn messages(&self, doc_ids:&[DocId]) -> io::Result<HashMap<DocId, LogEntry>> {
+        let mut wanted : Vec<Doc> = Vec::with_capacity(doc_ids.len());
+        let mut ids : HashMap<(FileId, u64), DocId> = HashMap::default();
+        for &doc_id in doc_ids {
+            let doc = self.docs[doc_id as usize];
+            wanted.push(doc);
+            ids.insert((doc.file, doc.offset), doc_id);
+        }
+        wanted.sort_by_key(|doc| (doc.file, doc.offset));
+        wanted.dedup_by_key(|doc| (doc.file, doc.offset));
+
+        let mut entries : HashMap<DocId, LogEntry> = HashMap::default();
+        let mut at = 0;
+        while at < wanted.len() {
+            let file_id = wanted[at].file;
+            let path = &self.files[file_id as usize].path;
+            let mut file = try!(File::open(path));
+            let mut position = wanted[at].offset;
+            try!(file.seek(SeekFrom::Start(position)));
+            let mut reader = BufReader::new(file);
+            let mut line = String::new();
+
+            while at < wanted.len() && wanted[at].file == file_id {
+                let offset = wanted[at].offset;
+                let missing = || Error::new(ErrorKind::InvalidData, format!("no message at {:?} offset {}", path, offset));
+                // lines in between are read past rather than sought over
+                loop {
+                    if position > offset {
+                        return Err(missing())
+                    }
+                    line.clear();
+                    let read = try!(reader.read_line(&mut line));
+                    if read == 0 {
+                        return Err(missing())
+                    }
+                    let start = position;
+                    position += read as u64;
+                    if start == offset {
+                        break
+                    }
+                }
+                let entry = try!(parse_log_line(&line).ok_or_else(&missing));
+                entries.insert(ids[&(file_id, offset)], entry);
+                at += 1;
+            }
+        }
+
+        Ok(entries)
+    }
+
+    pub fn result(&self, doc_id:DocId) -> io::Result<SearchResult> {
+        self.result_with_context(doc_id, 0)
+    }
+
+    // the message plus up to `context` messages either side of it in the same log
+    pub fn result_with_context(&self, doc_id:DocId, context:usize) -> io::Result<SearchResult> {
+        let entry = try!(self.message(doc_id));
+        let doc = self.docs[doc_id as usize];
+        let file = &self.files[doc.file as usize];
+
+        let mut result = SearchResult {
+            user_id: entry.user_id,
+            date: self.date(doc_id),
+            group: file.group,
+            username: entry.username.clone(),
+            forwarded: entry.is_forwarded(),
+            full_text: entry.text,
+            before: Vec::new(),
+            after: Vec::new(),
+        };
+
+        if context > 0 {
+            let siblings = &self.file_docs[doc.file as usize];
+            if let Ok(at) = siblings.binary_search(&doc_id) {
+                let start = at.saturating_sub(context);
+                let end = (at + 1 + context).min(siblings.len());
+                let mut around = try!(self.messages(&siblings[start..end]));
+                let mut context = |docs:&[DocId]| -> Vec<(UserId, String)> {
+                    docs.iter().filter_map(|d| around.remove(d)).map(|entry| (entry.user_id, entry.text)).collect()
+                };
+                result.before = context(&siblings[start..at]);
+                result.after = context(&siblings[at + 1..end]);
+            }
+        }
+
+        Ok(result)
+    }
+
+    pub fn save(&mut self) -> io::Result<()> {
+        let temp_path = self.path.with_extension("idx.tmp");
+        {
+            let mut out = BufWriter::new(try!(File::create(&temp_path)));
+            try!(writeln!(out, "{}", INDEX_HEADER));
+            for file in &self.files {
+                let group = file.group.map(|g| g.to_string()).unwrap_or_else(|| String::from("-"));
+                try!(writeln!(out, "file {} {} {}", file.indexed_len, group, file.path.to_string_lossy()));
+            }
+            for doc in &self.docs {
+                let date = doc.date.map(|d| d.format(DATE_FORMAT).to_string()).unwrap_or_else(|| String::from("-"));
+                try!(writeln!(out, "doc {} {} {} {} {}", doc.file, doc.offset, doc.user_id, doc.length, date));
+            }
+            for (term, list) in &self.postings {
+                let entries : Vec<String> = list.iter().map(|&(doc, tf)| format!("{}:{}", doc, tf)).collect();
+                try!(writeln!(out, "term {} {}", term, entries.join(" ")));
+            }
+            try!(out.flush());
+        }
+        try!(rename(&temp_path, &self.path));
+        self.unsaved = 0;
+        self.last_saved = Instant::now();
+        debug!("saved search index to {:?}", self.path);
+        Ok(())
+    }
+
+    fn load(path:&Path) -> io::Result<SearchIndex> {
+        let reader = BufReader::new(try!(File::open(path)));
+        let mut index = SearchIndex::empty(path);
+        let mut lines = reader.lines();
+
+        match lines.next() {
+            Some(Ok(ref header)) if header == INDEX_HEADER => (),
+            _ => return Err(corrupt("unknown header")),
+        }
+
+        for line_result in lines {
+            let line = try!(line_result);
+            let parts : Vec<&str> = line.splitn(2, ' ').collect();
+            let rest = parts.get(1).cloned().unwrap_or("");
+            match parts[0] {
+                "file" => {
+                    let fields : Vec<&str> = rest.splitn(3, ' ').collect();
+                    if fields.len() != 3 {
+                        return Err(corrupt("file line"))
+                    }
+                    let indexed_len = try!(fields[0].parse().map_err(|_| corrupt("file length")));
+                    let group = fields[1].parse().ok();
+                    let path = PathBuf::from(fields[2]);
+                    index.file_ids.insert(path.clone(), index.files.len() as FileId);
+                    index.files.push(LogFile { date: date_for_path(&path), path: path, group: group, indexed_len: indexed_len });
+                    index.file_docs.push(Vec::new());
+                },
+                "doc" => {
+                    let parts : Vec<&str> = rest.split(' ').collect();
+                    let fields : Vec<u64> = parts.iter().take(4).filter_map(|f| f.parse().ok()).collect();
+                    if parts.len() != 5 || fields.len() != 4 || fields[0] as usize >= index.files.len() {
+                        return Err(corrupt("doc line"))
+                    }
+                    let date = match parts[4] {
+                        "-" => None,
+                        date => Some(try!(NaiveDate::parse_from_str(date, DATE_FORMAT).map_err(|_| corrupt("doc date")))),
+                    };
+                    let doc_id = index.docs.len() as DocId;
+                    index.file_docs[fields[0] as usize].push(doc_id);
+                    index.docs.push(Doc { file: fields[0] as FileId, offset: fields[1], user_id: fields[2], length: fields[3] as u32, date: date });
+                    index.total_length += fields[3];
+                },
+                "term" => {
+                    let mut fields = rest.split(' ');
+                    let term = String::from(fields.next().unwrap_or(""));
+                    let mut list = Vec::new();
+                    // docs are saved before terms, a posting to one that isn't there yet is nonsense
+                    for entry in fields {
+                        let mut pair = entry.splitn(2, ':');
+                        match (pair.next().and_then(|d| d.parse().ok()), pair.next().and_then(|t| t.parse().ok())) {
+                            (Some(doc), Some(tf)) if (doc as usize) < index.docs.len() => list.push((doc, tf)),
+                            _ => return Err(corrupt("posting")),
+                        }
+                    }
+                    index.stems.entry(stem(&term)).or_insert_with(Vec::new).push(term.clone());
+                    index.postings.insert(term, list);
+                },
+                _ => return Err(corrupt("unknown line")),
+            }
+        }
+
+        Ok(index)
+    }
+}
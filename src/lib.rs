@@ -7,20 +7,37 @@ extern crate chrono;
 extern crate fnv;
 extern crate glob;
 extern crate rand;
+extern crate regex;
 #[macro_use]
 extern crate nom;
 #[macro_use]
 extern crate lazy_static;
+#[macro_use]
+extern crate log;
 
 pub mod persistence;
 pub mod model;
 pub mod history;
 pub mod tokenizer;
+pub mod marks;
 pub mod generate;
 pub mod command;
 pub mod search;
+pub mod index;
+pub mod html;
+pub mod stats;
+pub mod grep;
+pub mod migrate;
 pub mod bot;
 pub mod dice;
+pub mod trace;
+pub mod novelty;
+pub mod constraint;
+pub mod syllable;
+pub mod poetry;
+pub mod convo;
+pub mod fuzzy;
+pub mod stem;
 
 use fnv::FnvHasher;
 use std::collections::{HashMap as StdHashMap, HashSet as StdHashSet};
@@ -32,6 +49,13 @@ pub type HashSet<V> = StdHashSet<V, BuildHasherDefault<FnvHasher>>;
 
 use rand::{Rng, XorShiftRng, SeedableRng};
 
+pub type Seed = u32;
+
+// xorshift can't be seeded with all zeros, so the request seed is mixed with some fixed words
+pub fn seeded_rng(seed: Seed) -> XorShiftRng {
+    rand::XorShiftRng::from_seed([seed ^ 0x9e3779b9, 0x243f6a88, 0x85a308d3, 0x13198a2e])
+}
+
 pub fn unseeded_rng() -> XorShiftRng {
     let mut threaded_rng = rand::thread_rng();
     let random_seed = [threaded_rng.next_u32(), threaded_rng.next_u32(), threaded_rng.next_u32(), threaded_rng.next_u32()];
@@ -1,14 +1,42 @@
 extern crate robbot;
 extern crate chrono;
+extern crate env_logger;
+#[macro_use]
+extern crate log;
 
 use robbot::bot::*;
+use robbot::trace::open_trace;
 use std::time::{Instant, Duration};
 use std::thread;
 use std::cmp::min;
 
 use std::env;
+use std::path::Path;
+
+use env_logger::LogBuilder;
+use log::LogLevelFilter;
+
+// quiet by default, RUST_LOG=robbot::generate=trace etc. for more
+fn init_logging() {
+    let mut builder = LogBuilder::new();
+    builder.filter(None, LogLevelFilter::Warn);
+    builder.filter(Some("robbot"), LogLevelFilter::Info);
+    if let Ok(filters) = env::var("RUST_LOG") {
+        builder.parse(&filters);
+    }
+    builder.init().expect("a logger");
+}
 
 fn main() {
+    init_logging();
+
+    if let Ok(trace_path) = env::var("ROBBOT_TRACE") {
+        match open_trace(Path::new(&trace_path)) {
+            Ok(()) => info!("writing generation traces to {:?}", trace_path),
+            Err(e) => error!("couldn't open trace file {:?} -> {:?}", trace_path, e),
+        }
+    }
+
     let args : Vec<_> = env::args().collect();
     if let Some(key) = args.get(1) {
         let base_sleep_duration : u64 = 120;
@@ -16,29 +44,29 @@ fn main() {
 
         let fail_duration = Duration::new(120, 0);
 
-        println!("Building with key -> {:?}", key);
+        info!("Building bot");
         let mut bot = Bot::build(key, "../chat", "../history").expect("a bot");
-        println!("Entering main loop");
+        info!("Entering main loop");
         
         'main : loop {
-            println!("Starting");
+            info!("Starting");
             let pre_start = Instant::now();
             
             let res = bot.run();
-            println!("Run result -> {:?}", res);
+            warn!("Run result -> {:?}", res);
 
             let elapsed = pre_start.elapsed();
             if elapsed > fail_duration {
-                println!("Duration is longer than fails, just going to retry");
+                info!("Duration is longer than fails, just going to retry");
                 sequential_fails = 0;
             } else {
                 sequential_fails += 1;
                 let seconds_to_sleep = base_sleep_duration * 2u64.pow(min(10,sequential_fails));
-                println!("Failure to start, {:?} fails, sleeping for {:?}", sequential_fails, seconds_to_sleep);
+                warn!("Failure to start, {:?} fails, sleeping for {:?}", sequential_fails, seconds_to_sleep);
                 thread::sleep(Duration::new(seconds_to_sleep, 0));
             }
         }
     } else {
-        println!("Need to specify api key as arg")
+        error!("Need to specify api key as arg")
     }
 }
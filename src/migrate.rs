@@ -0,0 +1,204 @@
+use std::fs::*;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDate;
+use glob::glob;
+
+use super::persistence::{LogEntry, parse_log_line, split_legacy_line, unescape_message, is_legacy_line};
+use super::search::date_for_log;
+use super::index::INDEX_FILE;
+
+pub const BACKUP_EXTENSION : &'static str = "bak";
+const TEMP_EXTENSION : &'static str = "migrating";
+
+#[derive(Debug, Default)]
+pub struct FileReport {
+    pub lines: usize,
+    pub converted: usize,
+    // lines that didn't parse either way, copied over as they were
+    pub unreadable: usize,
+    // line numbers of legacy lines that read differently escaped or not, with no telling which, left as they were
+    pub ambiguous: Vec<usize>,
+}
+
+#[derive(Debug, Default)]
+pub struct MigrationReport {
+    pub migrated: Vec<(PathBuf, FileReport)>,
+    // already in the new format
+    pub skipped: Vec<PathBuf>,
+    // files with ambiguous lines still in them, and which lines
+    pub refused: Vec<(PathBuf, Vec<usize>)>,
+}
+
+// legacy lines have no time, so they get the start of the day their log is named for
+pub fn timestamp_for_date(date:NaiveDate) -> i64 {
+    date.and_hms(0, 0, 0).timestamp()
+}
+
+fn backup_path(path:&Path) -> PathBuf {
+    path.with_extension(format!("log.{}", BACKUP_EXTENSION))
+}
+
+fn temp_path(path:&Path) -> PathBuf {
+    path.with_extension(format!("log.{}", TEMP_EXTENSION))
+}
+
+// logs only escaped line breaks and backslashes from some day on, before that a "\n" in a line was just what was typed.
+// Some(true) for a log written escaped, Some(false) for one that wasn't, None when there's no knowing
+fn escaped_log(date:Option<NaiveDate>, escaped_since:Option<NaiveDate>) -> Option<bool> {
+    match (date, escaped_since) {
+        (Some(date), Some(since)) => Some(date >= since),
+        _ => None,
+    }
+}
+
+// how a bot that escaped would have stored text, to check an unescaped line against what's actually in the log
+fn escape_legacy(text:&str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn convert_line(line:&str, number:usize, date:Option<NaiveDate>, escaped:Option<bool>, report:&mut FileReport) -> String {
+    report.lines += 1;
+    if !is_legacy_line(line) {
+        return String::from(line)
+    }
+    let (user_id, stored) = match split_legacy_line(line) {
+        Some(split) => split,
+        None => {
+            report.unreadable += 1;
+            return String::from(line)
+        },
+    };
+
+    let unescaped = unescape_message(stored);
+    let text = if unescaped == stored {
+        unescaped // reads the same either way
+    } else {
+        match escaped {
+            Some(true) => unescaped,
+            Some(false) => String::from(stored),
+            None => {
+                report.ambiguous.push(number);
+                return String::from(line)
+            },
+        }
+    };
+
+    report.converted += 1;
+    let mut entry = LogEntry::new(user_id, &text);
+    entry.timestamp = date.map(timestamp_for_date);
+    let converted = entry.to_log_line();
+    String::from(converted.trim_right_matches('\n'))
+}
+
+// every converted line has to hold exactly the text that was stored, and everything else has to be untouched
+pub fn verify(original:&[String], converted_path:&Path, escaped:Option<bool>) -> io::Result<()> {
+    let reader = BufReader::new(try!(File::open(converted_path)));
+    let converted : Vec<String> = try!(reader.lines().collect());
+
+    if converted.len() != original.len() {
+        return Err(Error::new(ErrorKind::InvalidData, format!("{:?} has {} lines, the original had {}", converted_path, converted.len(), original.len())))
+    }
+
+    for (number, (before, after)) in original.iter().zip(converted.iter()).enumerate() {
+        let same = before == after || match (is_legacy_line(before), split_legacy_line(before), parse_log_line(after)) {
+            (true, Some((user_id, stored)), Some(ref entry)) if !is_legacy_line(after) => {
+                let stored_as = if escaped == Some(true) && unescape_message(stored) != stored {
+                    escape_legacy(&entry.text)
+                } else {
+                    entry.text.clone()
+                };
+                entry.user_id == user_id && stored_as == stored
+            },
+            _ => false,
+        };
+        if !same {
+            return Err(Error::new(ErrorKind::InvalidData, format!("{:?} line {} doesn't match the original", converted_path, number + 1)))
+        }
+    }
+
+    Ok(())
+}
+
+// rewrites one log in the new format, keeping the original as <name>.log.bak. Nothing to do gives None.
+// legacy lines in logs from escaped_since on are unescaped, ones that could go either way are refused
+pub fn migrate_file(path:&Path, dry_run:bool, escaped_since:Option<NaiveDate>) -> io::Result<Option<FileReport>> {
+    let reader = BufReader::new(try!(File::open(path)));
+    let original : Vec<String> = try!(reader.lines().collect());
+
+    // unreadable lines stay legacy looking forever, they don't make a file worth another pass
+    if !original.iter().any(|line| is_legacy_line(line) && parse_log_line(line).is_some()) {
+        return Ok(None)
+    }
+
+    let date = path.file_name().and_then(|n| n.to_str()).and_then(date_for_log);
+    let escaped = escaped_log(date, escaped_since);
+    let mut report = FileReport::default();
+    let converted : Vec<String> = original.iter().enumerate().map(|(i, line)| convert_line(line, i + 1, date, escaped, &mut report)).collect();
+
+    // only ambiguous lines left, rewriting would change nothing
+    if dry_run || report.converted == 0 {
+        return Ok(Some(report))
+    }
+
+    let temp = temp_path(path);
+    {
+        let mut out = BufWriter::new(try!(File::create(&temp)));
+        for line in &converted {
+            try!(writeln!(out, "{}", line));
+        }
+        try!(out.flush());
+    }
+    if let Err(e) = verify(&original, &temp, escaped) {
+        let _ = remove_file(&temp);
+        return Err(e)
+    }
+
+    // a backup left by an interrupted run is the real original, so it's kept
+    let backup = backup_path(path);
+    if backup.exists() {
+        info!("keeping existing backup {:?}", backup);
+    } else {
+        try!(copy(path, &backup));
+    }
+    try!(rename(&temp, path));
+
+    Ok(Some(report))
+}
+
+// every log under the chat root, group logs and the imported history.log
+pub fn migrate_root(root:&Path, dry_run:bool, escaped_since:Option<NaiveDate>) -> io::Result<MigrationReport> {
+    let pattern = format!("{}/**/*.log", root.to_string_lossy());
+    let paths : Vec<PathBuf> = try!(glob(&pattern).map_err(|e| Error::new(ErrorKind::InvalidInput, format!("{:?}", e))))
+        .filter_map(|p| p.ok())
+        .collect();
+
+    let mut report = MigrationReport::default();
+    for path in paths {
+        match try!(migrate_file(&path, dry_run, escaped_since)) {
+            Some(file_report) => {
+                info!("{:?}: {} lines, {} converted, {} unreadable", path, file_report.lines, file_report.converted, file_report.unreadable);
+                if !file_report.ambiguous.is_empty() {
+                    warn!("{:?}: lines {:?} may or may not be escaped, left as they were", path, file_report.ambiguous);
+                    report.refused.push((path.clone(), file_report.ambiguous.clone()));
+                }
+                if file_report.converted > 0 {
+                    report.migrated.push((path, file_report));
+                }
+            },
+            None => report.skipped.push(path),
+        }
+    }
+
+    // offsets into rewritten logs are meaningless, the bot rebuilds the index when it's missing
+    let index_path = root.join(INDEX_FILE);
+    if !dry_run && !report.migrated.is_empty() && index_path.exists() {
+        try!(remove_file(&index_path));
+        info!("removed search index {:?}, it'll be rebuilt on the next start", index_path);
+    }
+
+    Ok(report)
+}
@@ -8,6 +8,8 @@ use std::fmt;
 use std::path::PathBuf;
 
 use super::tokenizer::*;
+use super::novelty::*;
+use super::persistence::parse_log_line;
 
 
 pub type OccurenceCount = usize;
@@ -87,6 +89,16 @@ pub struct Model {
     pub tokens : Vec<Token>,
     pub users : HashMap<UserId, UserGenerativeModel>,
     pub shared : UserGenerativeModel,
+    // how often each user's message was directly followed by another user's, from log order
+    pub turns : HashMap<UserId, HashMap<UserId, OccurenceCount>>,
+    // values seen for each kind of number, to fill normalized numbers back in
+    pub numbers : HashMap<NumberKind, Vec<(String, OccurenceCount)>>,
+}
+
+impl Model {
+    pub fn line_indices(&self, line:&Line) -> Vec<TokenIdx> {
+        line.iter().map(|t| self.token_to_idx.get(t).cloned().unwrap_or(UNKNOWN_IDX)).collect()
+    }
 }
 
 // just for temporary storage
@@ -94,6 +106,7 @@ pub struct Model {
 struct UserLearningModel {
     pub own_bigrams : LearningModel<BigramContext>, 
     pub own_trigrams : LearningModel<TrigramContext>,
+    pub own_lines : LineIndex,
 }
 
 fn add<C : Eq + Hash + Copy>(sink: &mut TokenMap<C>, from: &TokenMap<C>) {
@@ -125,12 +138,14 @@ impl UserLearningModel {
         UserGenerativeModel {
             own_bigrams: self.own_bigrams.as_generative(min_count),
             own_trigrams: self.own_trigrams.as_generative(min_count),
+            own_lines: self.own_lines.clone(),
         }
     }
 
     pub fn add(&mut self, other: &UserLearningModel) {
         add(&mut self.own_bigrams.context_map, &other.own_bigrams.context_map);
         add(&mut self.own_trigrams.context_map, &other.own_trigrams.context_map);
+        self.own_lines.add(&other.own_lines);
     }
 }
 
@@ -145,6 +160,7 @@ impl Default for UserLearningModel {
                 context_map: HashMap::default(),
                 context_production: independent_trigram_context,
             },
+            own_lines: LineIndex::default(),
         }
     }
 }
@@ -153,6 +169,7 @@ impl Default for UserLearningModel {
 pub struct UserGenerativeModel {
     pub own_bigrams : GenerativeModel<BigramContext>, 
     pub own_trigrams : GenerativeModel<TrigramContext>,
+    pub own_lines : LineIndex,
 }
 
 
@@ -229,25 +246,18 @@ pub fn pack_table(word_map:&HashMap<usize, OccurenceCount>) -> CumulativeWordTab
 
 pub fn interesting_token(token:&Token) -> bool {
     match token {
-        &Token::Word(_) | &Token::Punctuation(_, _) | &Token::Link(_) | &Token::End | &Token::Start => true,
+        &Token::Word(_) | &Token::Punctuation(_, _) | &Token::Link(_, _) | &Token::Emoji(_) | &Token::End | &Token::Start => true,
+        &Token::Mention(_) | &Token::Hashtag(_) | &Token::Command(_) | &Token::Number(_, _) | &Token::Newline => true,
         // _ => false,
     }
 }
 
 pub fn leading_user_id(line:&str) -> Option<UserId> {
-    let at = line.find(" ").unwrap();
-    let (num, _) = line.split_at(at);
-    num.parse().ok()
+    parse_log_line(line).map(|entry| entry.user_id)
 } 
 
-pub fn parse_use_line(line:&str) -> (UserId, Vec<Token>) {
-    let at = line.find(" ").unwrap();
-    let (num, text) = line.split_at(at); 
-    let user_id: UserId = num.parse().expect("parsing user_id");
-
-    let tokens = tokenize_line(&text);
-
-    (user_id, tokens)
+pub fn parse_use_line(line:&str) -> Option<(UserId, Vec<Token>)> {
+    parse_log_line(line).map(|entry| (entry.user_id, tokenize_line(&entry.text.to_lowercase())))
 }
 
 pub fn create_models(paths:Vec<PathBuf>) -> Model {
@@ -255,7 +265,8 @@ pub fn create_models(paths:Vec<PathBuf>) -> Model {
     
     let mut token_map : HashMap<Token, usize> = HashMap::default();
     let mut all_tokens : Vec<Token> = Vec::new();
-
+    let mut turns : HashMap<UserId, HashMap<UserId, OccurenceCount>> = HashMap::default();
+    let mut number_values : HashMap<NumberKind, HashMap<String, OccurenceCount>> = HashMap::default();
     
     for path in paths {
         // println!("opening path {:?}", path);
@@ -264,11 +275,43 @@ pub fn create_models(paths:Vec<PathBuf>) -> Model {
         let lines = reader.lines();
 
         // let mut line_count = 0;
+        let mut last_speaker : Option<UserId> = None;
+        // who sent each message in this file, so replies can say who they're answering
+        let mut authors : HashMap<i64, UserId> = HashMap::default();
 
         for line_result in lines {
             // line_count += 1;
-            let line = line_result.expect("attempted to read a line in model").to_lowercase();
-            let (user_id, tokens) = parse_use_line(&line);
+            let line = line_result.expect("attempted to read a line in model");
+            let entry = match parse_log_line(&line) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let user_id = entry.user_id;
+            if let Some(message_id) = entry.message_id {
+                authors.insert(message_id, user_id);
+            }
+
+            // a reply is a turn taken from whoever it replies to, otherwise from whoever spoke last
+            let replied_to = entry.reply_to.and_then(|id| authors.get(&id).cloned());
+            if let Some(previous) = replied_to.or(last_speaker) {
+                if previous != user_id {
+                    *turns.entry(previous).or_insert_with(|| HashMap::default()).entry(user_id).or_insert(0) += 1;
+                }
+            }
+            last_speaker = Some(user_id);
+
+            // forwards are someone else's words, they'd only teach the model to quote
+            if entry.is_forwarded() {
+                continue
+            }
+            let raw_tokens = tokenize_line(&entry.text.to_lowercase());
+
+            for t in &raw_tokens {
+                if let &Token::Number(kind, ref value) = t {
+                    *number_values.entry(kind).or_insert_with(|| HashMap::default()).entry(value.clone()).or_insert(0) += 1;
+                }
+            }
+            let tokens : Vec<Token> = raw_tokens.iter().map(normalize_token).collect();
 
             // add token translation
             for t in &tokens {
@@ -287,6 +330,9 @@ pub fn create_models(paths:Vec<PathBuf>) -> Model {
                 user_model.own_bigrams.ingest(&tokens, idx, &token_map);
                 user_model.own_trigrams.ingest(&tokens, idx, &token_map);
             }
+
+            let token_idxs : Vec<TokenIdx> = tokens.iter().map(|t| token_map[t]).collect();
+            user_model.own_lines.ingest(&token_idxs);
         }
         // println!("had {} lines", line_count);
     }
@@ -295,25 +341,31 @@ pub fn create_models(paths:Vec<PathBuf>) -> Model {
 
     let mut shared_learning_model : UserLearningModel = UserLearningModel::default();
 
-    println!("building generative models");
+    info!("building generative models");
 
     for (user_id, learning_model) in user_models {
-        println!("constructing generative for {} ...", user_id);
+        debug!("constructing generative for {} ...", user_id);
         generative_user_models.insert(user_id, learning_model.as_generative(0));
-        println!("adding to shared ...");
+        debug!("adding {} to shared ...", user_id);
         shared_learning_model.add(&learning_model);
-        println!("done.");
     }
 
-
-    println!("Building shared generative ...");
+    info!("building shared generative ...");
     let shared_generative = shared_learning_model.as_generative(0);
-    println!("done.");
+    info!("built models, {} tokens, {} users", all_tokens.len(), generative_user_models.len());
+
+    let numbers = number_values.into_iter().map(|(kind, values)| {
+        let mut values : Vec<(String, OccurenceCount)> = values.into_iter().collect();
+        values.sort();
+        (kind, values)
+    }).collect();
 
     Model {
         token_to_idx: token_map,
         tokens: all_tokens,
         users: generative_user_models,
         shared: shared_generative,
+        turns: turns,
+        numbers: numbers,
     }
 } 
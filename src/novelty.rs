@@ -0,0 +1,75 @@
+use super::HashSet;
+use super::model::TokenIdx;
+
+use fnv::FnvHasher;
+use std::hash::{Hash, Hasher};
+
+// generated lines sharing a run this long with something actually said are treated as copies
+pub const COPY_NGRAM_LENGTH : usize = 6;
+
+// stands in for tokens that aren't in the vocabulary, so never matches anything learned
+pub const UNKNOWN_IDX : TokenIdx = ::std::usize::MAX;
+
+fn hash_run(run:&[TokenIdx]) -> u64 {
+    let mut hasher = FnvHasher::default();
+    run.hash(&mut hasher);
+    hasher.finish()
+}
+
+// hashes of every line (and every long n-gram) a user has said, for spotting verbatim copies
+#[derive(Debug, Clone)]
+pub struct LineIndex {
+    pub lines: HashSet<u64>,
+    pub ngrams: HashSet<u64>,
+}
+
+impl Default for LineIndex {
+    fn default() -> LineIndex {
+        LineIndex {
+            lines: HashSet::default(),
+            ngrams: HashSet::default(),
+        }
+    }
+}
+
+impl LineIndex {
+    pub fn ingest(&mut self, line:&[TokenIdx]) {
+        self.lines.insert(hash_run(line));
+        for run in line.windows(COPY_NGRAM_LENGTH) {
+            self.ngrams.insert(hash_run(run));
+        }
+    }
+
+    pub fn add(&mut self, other:&LineIndex) {
+        self.lines.extend(other.lines.iter().cloned());
+        self.ngrams.extend(other.ngrams.iter().cloned());
+    }
+
+    pub fn contains_line(&self, line:&[TokenIdx]) -> bool {
+        self.lines.contains(&hash_run(line))
+    }
+
+    // fraction of the n-grams ending at or after `from` that were seen in training
+    pub fn overlap(&self, line:&[TokenIdx], from:usize) -> f64 {
+        let mut total = 0;
+        let mut seen = 0;
+        for (i, run) in line.windows(COPY_NGRAM_LENGTH).enumerate() {
+            if i + COPY_NGRAM_LENGTH > from {
+                total += 1;
+                if self.ngrams.contains(&hash_run(run)) {
+                    seen += 1;
+                }
+            }
+        }
+        if total == 0 {
+            0.0
+        } else {
+            seen as f64 / total as f64
+        }
+    }
+
+    // novel means not an exact line, and no long run copied past the supplied prefix
+    pub fn is_novel(&self, line:&[TokenIdx], from:usize) -> bool {
+        !self.contains_line(line) && self.overlap(line, from) == 0.0
+    }
+}
@@ -1,10 +1,12 @@
 use chrono::*;
+use rustc_serialize::json::{Json, ToJson};
 
 use std::path::{PathBuf, Path};
 use std::fs::*;
 use std::io;
 use std::io::{Error, ErrorKind};
 use std::io::Write;
+use std::collections::BTreeMap;
 
 pub fn file_exists_at(path:&Path) -> bool {
     path.is_file() && path.exists()
@@ -21,8 +23,148 @@ pub fn ensure_directory(path:&Path) -> io::Result<()> {
     }  
 }
 
-pub fn clean_message(message:&str) -> String {
-    message.replace("\n"," ")
+// legacy lines are one message each, so line breaks were stored as \n (and backslashes as \\).
+// older logs were written before escaping, a stray "\n" in one of those will come back as a line break
+pub fn unescape_message(stored:&str) -> String {
+    let mut message = String::with_capacity(stored.len());
+    let mut chars = stored.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => message.push('\n'),
+                Some('\\') => message.push('\\'),
+                Some(other) => {
+                    message.push('\\');
+                    message.push(other);
+                },
+                None => message.push('\\'),
+            }
+        } else {
+            message.push(c);
+        }
+    }
+    message
+}
+
+// bumped whenever a field changes meaning, lines with a version we don't know are skipped
+pub const LOG_FORMAT_VERSION : u64 = 1;
+
+// one stored message. logs used to be "<user_id> <escaped text>" lines, which only have the first two
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    pub user_id: u64,
+    pub text: String,
+    // unix seconds
+    pub timestamp: Option<i64>,
+    pub message_id: Option<i64>,
+    pub reply_to: Option<i64>,
+    pub username: Option<String>,
+    // who it was forwarded from, the sender didn't write it
+    pub forward_from: Option<u64>,
+}
+
+impl LogEntry {
+    pub fn new(user_id:u64, text:&str) -> LogEntry {
+        LogEntry { user_id: user_id, text: String::from(text), timestamp: None, message_id: None, reply_to: None, username: None, forward_from: None }
+    }
+
+    pub fn is_forwarded(&self) -> bool {
+        self.forward_from.is_some()
+    }
+
+    pub fn date(&self) -> Option<NaiveDate> {
+        self.timestamp.and_then(|t| NaiveDateTime::from_timestamp_opt(t, 0)).map(|dt| dt.date())
+    }
+
+    // a single line of json, newlines in the text are escaped by the encoding
+    pub fn to_log_line(&self) -> String {
+        format!("{}\n", self.to_json())
+    }
+}
+
+impl ToJson for LogEntry {
+    fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+        object.insert(String::from("v"), LOG_FORMAT_VERSION.to_json());
+        object.insert(String::from("user"), self.user_id.to_json());
+        object.insert(String::from("text"), self.text.to_json());
+        if let Some(timestamp) = self.timestamp {
+            object.insert(String::from("at"), timestamp.to_json());
+        }
+        if let Some(message_id) = self.message_id {
+            object.insert(String::from("id"), message_id.to_json());
+        }
+        if let Some(reply_to) = self.reply_to {
+            object.insert(String::from("reply_to"), reply_to.to_json());
+        }
+        if let Some(ref username) = self.username {
+            object.insert(String::from("username"), username.to_json());
+        }
+        if let Some(forward_from) = self.forward_from {
+            object.insert(String::from("forward_from"), forward_from.to_json());
+        }
+        Json::Object(object)
+    }
+}
+
+fn parse_json_line(line:&str) -> Option<LogEntry> {
+    let json = match Json::from_str(line) {
+        Ok(json) => json,
+        Err(e) => {
+            warn!("unreadable log line {:?} -> {:?}", line, e);
+            return None
+        },
+    };
+
+    match json.find("v").and_then(|v| v.as_u64()) {
+        Some(LOG_FORMAT_VERSION) => (),
+        version => {
+            warn!("log line has unknown version {:?}", version);
+            return None
+        },
+    }
+
+    let user_id = json.find("user").and_then(|u| u.as_u64());
+    let text = json.find("text").and_then(|t| t.as_string());
+    match (user_id, text) {
+        (Some(user_id), Some(text)) => Some(LogEntry {
+            user_id: user_id,
+            text: String::from(text),
+            timestamp: json.find("at").and_then(|t| t.as_i64()),
+            message_id: json.find("id").and_then(|i| i.as_i64()),
+            reply_to: json.find("reply_to").and_then(|r| r.as_i64()),
+            username: json.find("username").and_then(|u| u.as_string()).map(String::from),
+            forward_from: json.find("forward_from").and_then(|f| f.as_u64()),
+        }),
+        _ => None,
+    }
+}
+
+// the user id and the text as it was stored, escapes and all
+pub fn split_legacy_line(line:&str) -> Option<(u64, &str)> {
+    let line = line.trim_right_matches(|c| c == '\n' || c == '\r');
+    line.find(' ').and_then(|at| {
+        let (num, text) = line.split_at(at);
+        num.parse().ok().map(|user_id| (user_id, &text[1..]))
+    })
+}
+
+fn parse_legacy_line(line:&str) -> Option<LogEntry> {
+    split_legacy_line(line).map(|(user_id, text)| LogEntry::new(user_id, &unescape_message(text)))
+}
+
+// either format, None for lines that don't parse
+pub fn parse_log_line(line:&str) -> Option<LogEntry> {
+    let line = line.trim_right_matches(|c| c == '\n' || c == '\r');
+    if line.starts_with('{') {
+        parse_json_line(line)
+    } else {
+        parse_legacy_line(line)
+    }
+}
+
+pub fn is_legacy_line(line:&str) -> bool {
+    !line.trim_left().starts_with('{')
 }
 
 pub struct Persistence {
@@ -35,7 +177,8 @@ impl Persistence {
         ensure_directory(path)
     }
 
-    pub fn store_chat_message(&self, group: u64, user: u64, message:&str) -> io::Result<()> {
+    // returns the log written to, and the offset and length of the new line in it
+    pub fn store_chat_message(&self, group: u64, entry:&LogEntry) -> io::Result<(PathBuf, u64, u64)> {
         let mut group_path = self.root_path.clone();
         group_path.push(group.to_string());
 
@@ -47,13 +190,13 @@ impl Persistence {
         group_path.push(format!("{}.log", date_string));
 
         let mut file = try!(OpenOptions::new().create(true).append(true).open(group_path.as_path()));
-        
-        let cleaned_message = clean_message(message);
-        let line = format!("{} {}\n", user, cleaned_message);
+        let offset = try!(file.metadata()).len();
+
+        let line = entry.to_log_line();
         try!(file.write_all(line.as_bytes()));
         
         try!(file.flush());
         
-        Ok(())
+        Ok((group_path, offset, line.len() as u64))
     }
 }
@@ -0,0 +1,48 @@
+use rand::Rng;
+
+use super::model::*;
+use super::generate::*;
+use super::syllable::*;
+use super::tokenizer::Token;
+
+pub const HAIKU : [usize; 3] = [5, 7, 5];
+
+// only lets through tokens that fit in what's left of a line's syllable budget
+pub struct SyllableBudget {
+    pub syllables: usize,
+}
+
+impl Steering for SyllableBudget {
+    fn weight(&self, model:&Model, line:&Line, token_idx:TokenIdx) -> f64 {
+        let used = line_syllables(line);
+        let token = &model.tokens[token_idx];
+        match (token, token_syllables(token)) {
+            (&Token::End, _) => if used == self.syllables { 1.0 } else { 0.0 },
+            (_, Some(n)) => if used + n <= self.syllables { 1.0 } else { 0.0 },
+            (_, None) => 0.0,
+        }
+    }
+
+    // a line that ran out of budget gets cut off by the last resort End, which is fine for poetry
+    fn accept(&self, candidate:&Candidate) -> bool {
+        line_syllables(&candidate.line) == self.syllables
+    }
+}
+
+pub fn generate_poem<R : Rng>(model:&Model, rng: &mut R, user_model:&UserGenerativeModel, config:&GenerationConfig, syllables:&[usize]) -> Option<Vec<String>> {
+    let sentence_start = vec!(Token::Start);
+    let mut lines = Vec::with_capacity(syllables.len());
+
+    for &n in syllables {
+        let budget = SyllableBudget { syllables: n };
+        match generate_best(model, rng, &sentence_start, user_model, config, &budget) {
+            Some(candidate) => lines.push(generate_sentence(&candidate.line)),
+            None => {
+                debug!("couldn't find a {} syllable line", n);
+                return None
+            },
+        }
+    }
+
+    Some(lines)
+}
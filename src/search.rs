@@ -1,67 +1,282 @@
-use std::io::BufReader;
-
-
-use std::fs::*;
-use std::io::BufRead;
-use std::path::PathBuf;
+use chrono::NaiveDate;
 
 use super::HashSet;
 use super::model::{UserId};
+use super::tokenizer::{Token, tokenize_line};
+use super::command::{all_user_ids, user_id_for_casual};
+
+#[derive(Debug, Clone)]
+pub struct SearchConfig {
+    // BM25 term frequency saturation and length normalisation
+    pub k1: f64,
+    pub b: f64,
+    // a message from today scores up to this much more (proportionally) than an ancient one
+    pub recency_weight: f64,
+    // days for the recency boost to halve
+    pub recency_half_life: f64,
+    // messages shown either side of each result
+    pub context: usize,
+    // results per reply, /more shows the next lot
+    pub page_size: usize,
+    // unquoted words also find other forms of themselves, "cats" finds "cat"
+    pub stemming: bool,
+    // most vocabulary words a ~word or stemmed word turns into
+    pub max_expansions: usize,
+}
+
+impl Default for SearchConfig {
+    fn default() -> SearchConfig {
+        SearchConfig {
+            k1: 1.2,
+            b: 0.75,
+            recency_weight: 0.5,
+            recency_half_life: 180.0,
+            context: 1,
+            page_size: 5,
+            stemming: true,
+            max_expansions: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Relevance,
+    Newest,
+    Oldest,
+}
 
 pub struct SearchResult {
     pub user_id: UserId,
     pub full_text: String,
+    // when it was sent, or the day of the log it came from
+    pub date: Option<NaiveDate>,
+    pub group: Option<u64>,
+    // as logged, for people the bot has no name for
+    pub username: Option<String>,
+    pub forwarded: bool,
+    // the messages around it, oldest first
+    pub before: Vec<(UserId, String)>,
+    pub after: Vec<(UserId, String)>,
 }
 
-pub fn pretty_search_result(text:&str, terms: &Vec<String>) -> String {
-    let mut res = String::from(text);
-    for term in terms {
-        let bolded_term = format!("<b>{}</b>", term);
-        res = res.replace(term, &bolded_term);
+// what a message is indexed under, queries go through the same thing so they line up
+pub fn search_terms(text:&str) -> Vec<String> {
+    let mut terms : Vec<String> = Vec::new();
+
+    for token in tokenize_line(&text.to_lowercase()) {
+        match token {
+            Token::Word(word) | Token::Emoji(word) | Token::Command(word) => terms.push(word),
+            Token::Mention(name) => terms.push(format!("@{}", name)),
+            Token::Hashtag(tag) => terms.push(format!("#{}", tag)),
+            Token::Number(_, value) => terms.push(value),
+            Token::Link(url, domain) => {
+                if url != domain {
+                    terms.push(domain);
+                }
+                terms.push(url);
+            },
+            Token::Punctuation(_, _) | Token::Newline | Token::Start | Token::End => (),
+        }
     }
-    res
+
+    terms
 }
 
-pub fn terms_for_search(text:&str) -> Vec<String> {
-    let mut terms : Vec<String> = Vec::new();
-    
-    for word in text.to_lowercase().split_whitespace() {
-        terms.push(String::from(word.trim()));
+// consecutive terms, a single word is a phrase of one
+pub type Phrase = Vec<String>;
+
+// a parsed /search, e.g. "pizza \"deep dish\" -pineapple cat OR dog from:robe after:2016-03"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    // every clause has to match, a clause matches if any of its phrases (joined by OR) does
+    pub required: Vec<Vec<Phrase>>,
+    pub excluded: Vec<Phrase>,
+    pub users: HashSet<UserId>,
+    // message dates, both exclusive
+    pub after: Option<NaiveDate>,
+    pub before: Option<NaiveDate>,
+    pub sort: SortOrder,
+    // "quoted" words that shouldn't be stemmed, and ~words to match with typos
+    pub exact: HashSet<String>,
+    pub fuzzy: HashSet<String>,
+}
+
+impl Query {
+    // every term that has to (or may) appear, for highlighting
+    pub fn terms(&self) -> Vec<String> {
+        let mut terms : Vec<String> = Vec::new();
+        for phrase in self.required.iter().flat_map(|clause| clause.iter()) {
+            for term in phrase {
+                if !terms.contains(term) {
+                    terms.push(term.clone());
+                }
+            }
+        }
+        terms
+    }
+
+    // phrases can only be checked against the text, single words are settled by the index
+    pub fn has_phrases(&self) -> bool {
+        self.required.iter().flat_map(|clause| clause.iter()).chain(self.excluded.iter()).any(|phrase| phrase.len() > 1)
+    }
+
+    pub fn matches(&self, terms:&[String]) -> bool {
+        self.required.iter().all(|clause| clause.iter().any(|phrase| contains_phrase(terms, phrase)))
+            && !self.excluded.iter().any(|phrase| contains_phrase(terms, phrase))
+    }
+
+    pub fn in_dates(&self, date:Option<NaiveDate>) -> bool {
+        match date {
+            Some(date) => self.after.map(|a| date > a).unwrap_or(true) && self.before.map(|b| date < b).unwrap_or(true),
+            None => self.after.is_none() && self.before.is_none(), // undated logs can't satisfy a date filter
+        }
+    }
+}
+
+pub fn contains_phrase(terms:&[String], phrase:&Phrase) -> bool {
+    !phrase.is_empty() && terms.windows(phrase.len()).any(|window| window == &phrase[..])
+}
+
+// 2016-03-12, or 2016-03 / 2016 for the whole month or year, as (first day, last day)
+pub fn parse_date_range(text:&str) -> Option<(NaiveDate, NaiveDate)> {
+    let parts : Vec<&str> = text.split('-').collect();
+    let numbers : Vec<u32> = parts.iter().filter_map(|p| p.parse().ok()).collect();
+    if numbers.len() != parts.len() {
+        return None
+    }
+
+    let year = numbers[0] as i32;
+    let (first, last) = match numbers.len() {
+        1 => (NaiveDate::from_ymd_opt(year, 1, 1), NaiveDate::from_ymd_opt(year, 12, 31)),
+        2 => {
+            let next_month = if numbers[1] == 12 { NaiveDate::from_ymd_opt(year + 1, 1, 1) } else { NaiveDate::from_ymd_opt(year, numbers[1] + 1, 1) };
+            (NaiveDate::from_ymd_opt(year, numbers[1], 1), next_month.and_then(|d| d.pred_opt()))
+        },
+        3 => (NaiveDate::from_ymd_opt(year, numbers[1], numbers[2]), NaiveDate::from_ymd_opt(year, numbers[1], numbers[2])),
+        _ => return None,
+    };
+
+    match (first, last) {
+        (Some(first), Some(last)) => Some((first, last)),
+        _ => None,
+    }
+}
+
+// the log a message came from is named after the day it was stored
+pub fn date_for_log(name:&str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(name.trim_right_matches(".log"), "%Y-%m-%d").ok()
+}
+
+// splits on whitespace, keeping "quoted phrases" together
+fn query_words(text:&str) -> Vec<String> {
+    let mut words : Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+
+    for c in text.chars() {
+        match c {
+            '"' | '“' | '”' => {
+                current.push('"');
+                quoted = !quoted;
+            },
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    words.push(current.clone());
+                    current.clear();
+                }
+            },
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        words.push(current);
     }
 
-    return terms
+    words
+}
+
+// for from:name, me is whoever is asking
+pub fn user_for_name(name:&str, asker:UserId) -> Result<UserId, String> {
+    if name == "me" {
+        Ok(asker)
+    } else {
+        user_id_for_casual(name).ok_or_else(|| format!("don't know who {:?} is", name))
+    }
 }
 
-pub fn search(paths:Vec<PathBuf>, terms: &Vec<String>, user_ids:&HashSet<UserId>) -> Vec<SearchResult> {
-    let mut results : Vec<SearchResult> = Vec::new();
-
-    for path in paths {
-        // println!("opening path {:?}", path);
-        let file = File::open(path).unwrap();
-        let reader = BufReader::new(file);
-        let lines = reader.lines();
-
-        for line_result in lines {
-            let line = line_result.expect("attempted to read a line in model").to_lowercase();
-            let at = line.find(" ").unwrap();
-            let (num, text) = line.split_at(at);
-            let maybe_user_id : Option<UserId> = num.parse().ok();
-            if let Some(user_id) = maybe_user_id {
-                if user_ids.contains(&user_id) {
-                    let lowercase_text = text.to_lowercase(); 
-                    let ok = terms.iter().all(|t| lowercase_text.contains(t));
-                    if ok {
-                        let result = SearchResult { 
-                            user_id: user_id, 
-                            full_text: lowercase_text 
-                        };
-                        // add context
-                        results.push(result);
-                    }
+pub fn parse_query(text:&str, asker:UserId) -> Result<Query, String> {
+    let mut query = Query { required: Vec::new(), excluded: Vec::new(), users: HashSet::default(), after: None, before: None, sort: SortOrder::Relevance, exact: HashSet::default(), fuzzy: HashSet::default() };
+    let mut or_next = false;
+
+    for word in query_words(text) {
+        if word == "OR" || word == "|" {
+            if query.required.is_empty() {
+                return Err(String::from("OR needs something on both sides"))
+            }
+            or_next = true;
+            continue
+        }
+
+        let lowercase = word.to_lowercase();
+        let parts : Vec<&str> = lowercase.splitn(2, ':').collect();
+        match (parts[0], parts.get(1)) {
+            ("from", Some(name)) if !name.is_empty() => {
+                query.users.insert(try!(user_for_name(name, asker)));
+                continue
+            },
+            ("before", Some(date)) | ("after", Some(date)) => {
+                match parse_date_range(date) {
+                    Some((first, last)) => if parts[0] == "before" { query.before = Some(first) } else { query.after = Some(last) },
+                    None => return Err(format!("{}: wants a date like 2016-03-12, 2016-03 or 2016, not {:?}", parts[0], date)),
                 }
+                continue
+            },
+            ("sort", Some(order)) => {
+                query.sort = match *order {
+                    "new" | "newest" => SortOrder::Newest,
+                    "old" | "oldest" => SortOrder::Oldest,
+                    "best" => SortOrder::Relevance,
+                    _ => return Err(format!("sort: can be new, old or best, not {:?}", order)),
+                };
+                continue
+            },
+            _ => (),
+        }
+
+        let negated = word.len() > 1 && word.starts_with('-');
+        let unnegated = word.trim_left_matches('-');
+        let phrase : Phrase = search_terms(unnegated.trim_left_matches('~').trim_matches('"'));
+        if phrase.is_empty() {
+            continue
+        }
+        if phrase.len() == 1 {
+            if unnegated.starts_with('~') {
+                query.fuzzy.insert(phrase[0].clone());
+            } else if unnegated.starts_with('"') {
+                query.exact.insert(phrase[0].clone());
             }
         }
+
+        if negated {
+            query.excluded.push(phrase);
+        } else if or_next {
+            query.required.last_mut().unwrap().push(phrase);
+        } else {
+            query.required.push(vec![phrase]);
+        }
+        or_next = false;
+    }
+
+    if or_next {
+        return Err(String::from("OR needs something on both sides"))
     }
-    
-    return results
-}
\ No newline at end of file
+    if query.required.is_empty() && query.excluded.is_empty() && query.users.is_empty() && query.after.is_none() && query.before.is_none() {
+        return Err(String::from("Search for what? e.g. /search pizza -pineapple from:robe"))
+    }
+    if query.users.is_empty() {
+        query.users = all_user_ids().into_iter().collect();
+    }
+
+    Ok(query)
+}
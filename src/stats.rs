@@ -0,0 +1,87 @@
+use chrono::Datelike;
+
+use super::HashMap;
+use super::model::UserId;
+use super::index::{SearchIndex, DocId};
+use super::search::Query;
+
+const SPARKS : [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+pub type Month = (i32, u32);
+
+// the group's own logs, plus the imported history which has no group
+pub fn in_group(index:&SearchIndex, doc_id:DocId, group_id:u64) -> bool {
+    let file = &index.files[index.docs[doc_id as usize].file as usize];
+    file.group.map(|g| g == group_id).unwrap_or(true)
+}
+
+// how many times the query's words turn up in a message, a message only matching a phrase counts once
+pub fn occurrences(index:&SearchIndex, doc_id:DocId, query:&Query) -> u32 {
+    let mut count = 0;
+    for phrase in query.required.iter().flat_map(|clause| clause.iter()).filter(|phrase| phrase.len() == 1) {
+        if let Some(list) = index.postings.get(&phrase[0]) {
+            if let Ok(at) = list.binary_search_by_key(&doc_id, |&(doc, _)| doc) {
+                count += list[at].1;
+            }
+        }
+    }
+    count.max(1)
+}
+
+// (user, occurrences, messages), most occurrences first
+pub fn count_by_user(index:&SearchIndex, docs:&[DocId], query:&Query) -> Vec<(UserId, u32, u32)> {
+    let mut counts : HashMap<UserId, (u32, u32)> = HashMap::default();
+    for &doc in docs {
+        let entry = counts.entry(index.docs[doc as usize].user_id).or_insert((0, 0));
+        entry.0 += occurrences(index, doc, query);
+        entry.1 += 1;
+    }
+
+    let mut counts : Vec<(UserId, u32, u32)> = counts.into_iter().map(|(user_id, (n, messages))| (user_id, n, messages)).collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts
+}
+
+fn next_month(month:Month) -> Month {
+    if month.1 == 12 { (month.0 + 1, 1) } else { (month.0, month.1 + 1) }
+}
+
+// occurrences for every month from the first to the last dated match, empty months included
+pub fn count_by_month(index:&SearchIndex, docs:&[DocId], query:&Query) -> Vec<(Month, u32)> {
+    let mut counts : HashMap<Month, u32> = HashMap::default();
+    for &doc in docs {
+        if let Some(date) = index.date(doc) {
+            *counts.entry((date.year(), date.month())).or_insert(0) += occurrences(index, doc, query);
+        }
+    }
+
+    let (first, last) = match (counts.keys().min(), counts.keys().max()) {
+        (Some(&first), Some(&last)) => (first, last),
+        _ => return Vec::new(),
+    };
+
+    let mut months = Vec::new();
+    let mut month = first;
+    while month <= last {
+        months.push((month, counts.get(&month).cloned().unwrap_or(0)));
+        month = next_month(month);
+    }
+    months
+}
+
+// one bar per value, scaled to the biggest. the lowest bar is kept for nothing at all
+pub fn sparkline(values:&[u32]) -> String {
+    let max = values.iter().cloned().max().unwrap_or(0);
+    values.iter().map(|&v| {
+        if v == 0 {
+            SPARKS[0]
+        } else {
+            let level = 1 + ((v as f64 / max as f64) * (SPARKS.len() - 2) as f64).round() as usize;
+            SPARKS[level.min(SPARKS.len() - 1)]
+        }
+    }).collect()
+}
+
+pub fn format_month(month:Month) -> String {
+    format!("{}-{:02}", month.0, month.1)
+}
@@ -0,0 +1,180 @@
+// porter's stemmer, "cats" -> "cat", "arguing" "argued" -> "argu".
+// stems aren't words, they're only compared with each other
+
+fn is_consonant(word:&[u8], i:usize) -> bool {
+    match word[i] {
+        b'a' | b'e' | b'i' | b'o' | b'u' => false,
+        b'y' => i == 0 || !is_consonant(word, i - 1),
+        _ => true,
+    }
+}
+
+// m in [C](VC)^m[V], the number of vowel-consonant runs
+fn measure(word:&[u8]) -> usize {
+    let mut m = 0;
+    let mut i = 0;
+    let n = word.len();
+    while i < n && is_consonant(word, i) {
+        i += 1;
+    }
+    loop {
+        while i < n && !is_consonant(word, i) {
+            i += 1;
+        }
+        if i >= n {
+            return m
+        }
+        while i < n && is_consonant(word, i) {
+            i += 1;
+        }
+        m += 1;
+    }
+}
+
+fn has_vowel(word:&[u8]) -> bool {
+    (0..word.len()).any(|i| !is_consonant(word, i))
+}
+
+fn ends_double_consonant(word:&[u8]) -> bool {
+    let n = word.len();
+    n >= 2 && word[n - 1] == word[n - 2] && is_consonant(word, n - 1)
+}
+
+// consonant-vowel-consonant, where the last isn't w, x or y, "hop" but not "snow"
+fn ends_cvc(word:&[u8]) -> bool {
+    let n = word.len();
+    n >= 3 && is_consonant(word, n - 3) && !is_consonant(word, n - 2) && is_consonant(word, n - 1)
+        && word[n - 1] != b'w' && word[n - 1] != b'x' && word[n - 1] != b'y'
+}
+
+// swaps the suffix if what's left has a measure over min_measure. Some(replaced) if the suffix was there at all
+fn replace_suffix(word:&mut Vec<u8>, suffix:&str, replacement:&str, min_measure:usize) -> Option<bool> {
+    if !word.ends_with(suffix.as_bytes()) {
+        return None
+    }
+    let stem_len = word.len() - suffix.len();
+    if measure(&word[..stem_len]) > min_measure {
+        word.truncate(stem_len);
+        word.extend_from_slice(replacement.as_bytes());
+        Some(true)
+    } else {
+        Some(false)
+    }
+}
+
+// the first matching suffix decides, whether or not it gets replaced
+fn replace_first(word:&mut Vec<u8>, rules:&[(&str, &str)], min_measure:usize) {
+    for &(suffix, replacement) in rules {
+        if replace_suffix(word, suffix, replacement, min_measure).is_some() {
+            return
+        }
+    }
+}
+
+fn step_1a(word:&mut Vec<u8>) {
+    if word.ends_with(b"sses") || word.ends_with(b"ies") {
+        let n = word.len();
+        word.truncate(n - 2);
+    } else if word.ends_with(b"s") && !word.ends_with(b"ss") {
+        word.pop();
+    }
+}
+
+fn step_1b(word:&mut Vec<u8>) {
+    if word.ends_with(b"eed") {
+        replace_suffix(word, "eed", "ee", 0);
+        return
+    }
+
+    let removed = if word.ends_with(b"ed") && has_vowel(&word[..word.len() - 2]) {
+        let n = word.len();
+        word.truncate(n - 2);
+        true
+    } else if word.ends_with(b"ing") && has_vowel(&word[..word.len() - 3]) {
+        let n = word.len();
+        word.truncate(n - 3);
+        true
+    } else {
+        false
+    };
+
+    if removed {
+        if word.ends_with(b"at") || word.ends_with(b"bl") || word.ends_with(b"iz") {
+            word.push(b'e');
+        } else if ends_double_consonant(word) && !(word.ends_with(b"l") || word.ends_with(b"s") || word.ends_with(b"z")) {
+            word.pop();
+        } else if measure(word) == 1 && ends_cvc(word) {
+            word.push(b'e');
+        }
+    }
+}
+
+fn step_1c(word:&mut Vec<u8>) {
+    let n = word.len();
+    if word.ends_with(b"y") && has_vowel(&word[..n - 1]) {
+        word[n - 1] = b'i';
+    }
+}
+
+const STEP_2 : &'static [(&'static str, &'static str)] = &[
+    ("ational", "ate"), ("tional", "tion"), ("enci", "ence"), ("anci", "ance"), ("izer", "ize"),
+    ("abli", "able"), ("alli", "al"), ("entli", "ent"), ("eli", "e"), ("ousli", "ous"),
+    ("ization", "ize"), ("ation", "ate"), ("ator", "ate"), ("alism", "al"), ("iveness", "ive"),
+    ("fulness", "ful"), ("ousness", "ous"), ("aliti", "al"), ("iviti", "ive"), ("biliti", "ble"),
+];
+
+const STEP_3 : &'static [(&'static str, &'static str)] = &[
+    ("icate", "ic"), ("ative", ""), ("alize", "al"), ("iciti", "ic"), ("ical", "ic"), ("ful", ""), ("ness", ""),
+];
+
+// longest first, so "ement" gets a look before "ment" and "ent"
+const STEP_4 : &'static [&'static str] = &[
+    "ement", "ance", "ence", "able", "ible", "ment", "ant", "ent", "ion", "ism",
+    "ate", "iti", "ous", "ive", "ize", "al", "er", "ic", "ou",
+];
+
+fn step_4(word:&mut Vec<u8>) {
+    for &suffix in STEP_4 {
+        if !word.ends_with(suffix.as_bytes()) {
+            continue
+        }
+        let stem_len = word.len() - suffix.len();
+        // "ion" only comes off after an s or t
+        let allowed = suffix != "ion" || (stem_len > 0 && (word[stem_len - 1] == b's' || word[stem_len - 1] == b't'));
+        if allowed && measure(&word[..stem_len]) > 1 {
+            word.truncate(stem_len);
+        }
+        return
+    }
+}
+
+fn step_5(word:&mut Vec<u8>) {
+    if word.ends_with(b"e") {
+        let stem = &word[..word.len() - 1];
+        let m = measure(stem);
+        if m > 1 || (m == 1 && !ends_cvc(stem)) {
+            word.pop();
+        }
+    }
+    if measure(word) > 1 && ends_double_consonant(word) && word.ends_with(b"l") {
+        word.pop();
+    }
+}
+
+// only plain lowercase ascii words get stemmed, anything else comes back as it was
+pub fn stem(word:&str) -> String {
+    if word.len() <= 2 || !word.bytes().all(|b| b >= b'a' && b <= b'z') {
+        return String::from(word)
+    }
+
+    let mut bytes : Vec<u8> = word.bytes().collect();
+    step_1a(&mut bytes);
+    step_1b(&mut bytes);
+    step_1c(&mut bytes);
+    replace_first(&mut bytes, STEP_2, 0);
+    replace_first(&mut bytes, STEP_3, 0);
+    step_4(&mut bytes);
+    step_5(&mut bytes);
+
+    String::from_utf8(bytes).unwrap_or_else(|_| String::from(word))
+}
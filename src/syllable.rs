@@ -0,0 +1,98 @@
+use super::tokenizer::Token;
+
+fn is_vowel(c:char) -> bool {
+    "aeiouy".contains(c)
+}
+
+// words the rules below get wrong and that turn up often enough to matter
+const IRREGULAR : [(&'static str, usize); 2] = [("business", 2), ("every", 2)];
+
+// whether the vowel pair starting at `at` is said as two syllables, "vi-a", "i-de-a", "po-em"
+fn splits_pair(chars:&[char], at:usize) -> bool {
+    let n = chars.len();
+    let before = if at > 0 { Some(chars[at - 1]) } else { None };
+    let after = chars.get(at + 2).cloned();
+    match (chars[at], chars[at + 1]) {
+        // "special", "musician"
+        ('i', 'a') => before != Some('c') && before != Some('t'),
+        // "nation", "region", "precious", but "radio"
+        ('i', 'o') => !(before.map(|c| "cgst".contains(c)).unwrap_or(false) && (after == Some('n') || after == Some('u'))),
+        // "quality", "language"
+        ('u', 'a') | ('u', 'o') => before != Some('q') && before != Some('g'),
+        // "people", "pigeon", "luncheon", but "neon" and "geology"
+        ('e', 'o') => after != Some('p') && !(at > 1 && (before == Some('g') || chars[at - 2..at] == ['c', 'h'])),
+        // "idea", "area", "create", but "sea" and "great"
+        ('e', 'a') => {
+            let vowel_before = chars[..at].iter().any(|&c| is_vowel(c));
+            (at + 2 == n && vowel_before) || (at + 4 == n && chars[at + 2..] == ['t', 'e'])
+        },
+        // "poem", "poet", but "toe" and "goes"
+        ('o', 'e') => at + 2 < n && !(at + 3 == n && after == Some('s')),
+        _ => false,
+    }
+}
+
+// heuristic english syllable count, good enough for haiku rather than dictionaries
+pub fn count_syllables(word:&str) -> usize {
+    let letters : String = word.to_lowercase().chars().filter(|c| c.is_alphabetic()).collect();
+    if letters.is_empty() {
+        return 0
+    }
+    if let Some(&(_, count)) = IRREGULAR.iter().find(|&&(irregular, _)| irregular == letters) {
+        return count
+    }
+    // "lateness" is "late" and "ness", the stem's silent e is still silent
+    if letters.len() > 6 && letters.ends_with("ness") {
+        return count_syllables(&letters[..letters.len() - 4]) + 1
+    }
+
+    let chars : Vec<char> = letters.chars().collect();
+    let mut count = 0;
+    let mut in_group = false;
+    for (i, &c) in chars.iter().enumerate() {
+        let vowel = is_vowel(c) && !(c == 'y' && i == 0);
+        if vowel && !in_group {
+            count += 1;
+        } else if vowel && splits_pair(&chars, i - 1) {
+            count += 1;
+        }
+        in_group = vowel;
+    }
+
+    let n = chars.len();
+    let consonant_before = |i:usize| i > 0 && !is_vowel(chars[i - 1]);
+
+    if n > 2 && letters.ends_with('e') && !letters.ends_with("le") && !letters.ends_with("ee") && consonant_before(n - 1) {
+        count -= 1; // silent e, "make"
+    } else if n > 3 && letters.ends_with("le") && !consonant_before(n - 2) {
+        count -= 1; // "whale", but not "table"
+    } else if n > 3 && letters.ends_with("ed") && !letters.ends_with("ted") && !letters.ends_with("ded") && consonant_before(n - 2) {
+        count -= 1; // "jumped"
+    } else if n > 3 && letters.ends_with("es") && consonant_before(n - 2) {
+        let sibilant = ["ses", "xes", "zes", "ches", "shes", "ges", "ces"].iter().any(|s| letters.ends_with(s));
+        if !sibilant {
+            count -= 1; // "makes"
+        }
+    }
+
+    if count == 0 { 1 } else { count }
+}
+
+// None for tokens that can't sensibly be counted (links, numbers)
+pub fn token_syllables(token:&Token) -> Option<usize> {
+    match token {
+        &Token::Word(ref word) => {
+            if word.chars().any(|c| c.is_numeric()) {
+                None
+            } else {
+                Some(count_syllables(word))
+            }
+        },
+        &Token::Punctuation(_, _) | &Token::Start | &Token::End => Some(0),
+        _ => None,
+    }
+}
+
+pub fn line_syllables(line:&Vec<Token>) -> usize {
+    line.iter().filter_map(token_syllables).sum()
+}
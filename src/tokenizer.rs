@@ -1,8 +1,11 @@
 use super::{HashSet};
 use std::hash::Hash;
 use std::ops::Range;
+use std::cmp::Ordering;
 use std::fmt;
 
+use super::marks::MARK_RANGES;
+
 pub type Charset = HashSet<char>;
 pub type WordSet = HashSet<String>;
 
@@ -21,20 +24,188 @@ pub fn word_set(words:Vec<&str>) -> WordSet {
 }
 
 lazy_static! {
-    static ref DIGITS : Charset = char_range('0','9');
     static ref PUNCTUATION : Charset = {
-        vec!(',', '.', '(', ')', ':', ';', '/', '-', '?').into_iter().collect()
+        vec!(',', '.', '(', ')', '[', ']', '{', '}', ':', ';', '/', '-', '&', '?', '!', '"', '\'',
+             '\u{201C}', '\u{201D}', '\u{2018}', '\u{AB}', '\u{BB}', '\u{BF}', '\u{A1}', '\u{2026}', '\u{2013}', '\u{2014}',
+             '\u{3001}', '\u{3002}', '\u{FF01}', '\u{FF0C}', '\u{FF1F}').into_iter().collect()
     };
-    static ref LOWER_LETTERS : Charset = char_range('a','z');
-    static ref UPPER_LETTERS : Charset = char_range('A', 'Z');
-    static ref START_WORD : Charset = union(&union(&UPPER_LETTERS, &LOWER_LETTERS), &DIGITS);
     static ref CONTINUE_EXTRA : Charset =  {
-        vec!('\'', '_').into_iter().collect() // apostrophe
+        vec!('\'', '_', '\u{2019}').into_iter().collect() // apostrophes
+    };
+    // top level domains trusted enough to spot a link with no scheme, skipping ones that are also common words
+    static ref BARE_DOMAIN_TLDS : WordSet = word_set(vec!(
+        "com", "org", "net", "edu", "gov", "mil", "int", "io", "co", "ai", "app", "dev", "tv", "fm", "gg", "ly",
+        "info", "biz", "xyz", "online", "site", "tech", "blog", "news", "wiki", "uk", "au", "nz", "ca", "de",
+        "fr", "jp", "ru", "nl", "es", "se", "ch", "eu", "cn", "br", "pl", "ie", "za", "kr", "gl", "tk"));
+}
+
+const URL_SCHEMES : [&'static str; 4] = ["http://", "https://", "ftp://", "mailto:"];
+
+// trailing punctuation that almost always belongs to the sentence rather than the link
+fn trims_from_link(c:char, link:&str) -> bool {
+    match c {
+        '.' | ',' | ';' | ':' | '!' | '?' | '\'' | '"' | '\u{201D}' | '\u{2019}' => true,
+        ')' => link.matches('(').count() < link.matches(')').count(),
+        ']' => link.matches('[').count() < link.matches(']').count(),
+        _ => false,
+    }
+}
+
+fn is_host_label(label:&str) -> bool {
+    !label.is_empty() && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+}
+
+// bare "example.com/foo" or "bob@example.com", the tld has to be one we recognise
+fn is_bare_link(candidate:&str) -> bool {
+    let without_user = match candidate.find('@') {
+        Some(at) => {
+            let user = &candidate[..at];
+            if user.is_empty() || !user.chars().all(|c| c.is_alphanumeric() || ".-_+".contains(c)) {
+                return false
+            }
+            &candidate[at + 1..]
+        },
+        None => candidate,
+    };
+    let host_end = without_user.find(|c| c == '/' || c == '?' || c == '#' || c == ':').unwrap_or(without_user.len());
+    let host = &without_user[..host_end];
+    let labels : Vec<&str> = host.split('.').collect();
+
+    labels.len() >= 2 && labels.iter().all(|l| is_host_label(l)) && BARE_DOMAIN_TLDS.contains(&labels[labels.len() - 1].to_lowercase())
+}
+
+// byte length of the link at the start of text, if there is one
+pub fn link_length(text:&str) -> Option<usize> {
+    let chunk_end = text.find(char::is_whitespace).unwrap_or(text.len());
+    let mut link = &text[..chunk_end];
+
+    while let Some(last) = link.chars().last() {
+        if trims_from_link(last, link) {
+            link = &link[..link.len() - last.len_utf8()];
+        } else {
+            break;
+        }
+    }
+
+    let lower = link.to_lowercase();
+    let prefix = URL_SCHEMES.iter().chain(["www."].iter()).find(|s| lower.starts_with(*s));
+
+    let ok = match prefix {
+        Some(prefix) => link.len() > prefix.len() && link_domain(link).contains('.'),
+        None => is_bare_link(link),
     };
-    static ref CONTINUE_WORD : Charset = union(&START_WORD, &CONTINUE_EXTRA);
-    static ref LINK_WORDS : WordSet = word_set(vec!("http", "https", "www"));
+
+    if ok { Some(link.len()) } else { None }
+}
+
+// "https://www.Example.com:8080/foo" -> "example.com", "mailto:bob@example.com" -> "example.com"
+pub fn link_domain(link:&str) -> String {
+    let lower = link.to_lowercase();
+    let without_scheme = match lower.find("://") {
+        Some(at) => &lower[at + 3..],
+        None if lower.starts_with("mailto:") => &lower["mailto:".len()..],
+        None => &lower[..],
+    };
+    let host_end = without_scheme.find(|c| c == '/' || c == '?' || c == '#').unwrap_or(without_scheme.len());
+    let authority = &without_scheme[..host_end];
+    let host = match authority.rfind('@') {
+        Some(at) => &authority[at + 1..],
+        None => authority,
+    };
+    let host = host.split(':').next().unwrap_or(host);
+    let host = if host.starts_with("www.") { &host[4..] } else { host };
+    String::from(host)
+}
+
+// combining marks (accents written as separate code points, indic vowel signs and viramas etc)
+pub fn is_mark(c:char) -> bool {
+    MARK_RANGES.binary_search_by(|&(from, to)| {
+        if to < c { Ordering::Less } else if from > c { Ordering::Greater } else { Ordering::Equal }
+    }).is_ok()
 }
 
+// scripts written without spaces, where every character is modelled as its own word
+pub fn is_ideograph(c:char) -> bool {
+    match c {
+        '\u{3040}'...'\u{30FF}' | '\u{3400}'...'\u{4DBF}' | '\u{4E00}'...'\u{9FFF}' |
+        '\u{F900}'...'\u{FAFF}' | '\u{20000}'...'\u{2FFFF}' => true,
+        _ => false,
+    }
+}
+
+const ZERO_WIDTH_JOINER : char = '\u{200D}';
+
+pub fn is_regional_indicator(c:char) -> bool {
+    c >= '\u{1F1E6}' && c <= '\u{1F1FF}'
+}
+
+// anything that can begin an emoji
+pub fn is_emoji(c:char) -> bool {
+    match c {
+        '\u{1F000}'...'\u{1F02F}' | '\u{1F0CF}' | '\u{1F170}'...'\u{1F251}' |
+        '\u{1F300}'...'\u{1F64F}' | '\u{1F680}'...'\u{1F6FF}' | '\u{1F900}'...'\u{1F9FF}' |
+        '\u{1FA70}'...'\u{1FAFF}' | '\u{2600}'...'\u{27BF}' | '\u{2B50}' | '\u{2B55}' |
+        '\u{231A}'...'\u{231B}' | '\u{23E9}'...'\u{23FA}' => true,
+        _ => is_regional_indicator(c),
+    }
+}
+
+// variation selectors, skin tones, keycaps and tags, which attach to the emoji before them
+pub fn is_emoji_modifier(c:char) -> bool {
+    match c {
+        '\u{FE0E}' | '\u{FE0F}' | '\u{1F3FB}'...'\u{1F3FF}' | '\u{20E3}' | '\u{E0020}'...'\u{E007F}' => true,
+        _ => false,
+    }
+}
+
+// whether c extends the emoji sequence so far, joined families, toned hands and two letter flags are one token
+fn continues_emoji(sequence:&str, c:char) -> bool {
+    let last = sequence.chars().last();
+    if is_emoji_modifier(c) || c == ZERO_WIDTH_JOINER {
+        true
+    } else if last == Some(ZERO_WIDTH_JOINER) {
+        is_emoji(c)
+    } else if is_regional_indicator(c) {
+        sequence.chars().count() == 1 && last.map(is_regional_indicator).unwrap_or(false)
+    } else {
+        false
+    }
+}
+
+// letters and digits in any script
+pub fn starts_word(c:char) -> bool {
+    c.is_alphanumeric() && !is_ideograph(c)
+}
+
+pub fn continues_word(c:char) -> bool {
+    starts_word(c) || is_mark(c) || CONTINUE_EXTRA.contains(&c)
+}
+
+
+// how a punctuation character wants to be spaced when turning tokens back into text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PunctuationClass {
+    Opening, // "(", space before but not after
+    Closing, // ")", "." etc, hugs whatever came before
+    Joining, // "-", "/", spaced on both sides or neither
+    Quote, // opening or closing depending on whether one is already open
+}
+
+pub fn punctuation_class(c:char) -> PunctuationClass {
+    use self::PunctuationClass::*;
+    match c {
+        '(' | '[' | '{' | '\u{201C}' | '\u{2018}' | '\u{AB}' | '\u{BF}' | '\u{A1}' => Opening,
+        '-' | '/' | '&' | '\u{2013}' | '\u{2014}' => Joining,
+        '"' | '\'' => Quote,
+        _ => Closing,
+    }
+}
+
+// an ellipsis trails off, and a quoted question is usually followed by "he asked"
+pub fn ends_sentence(punc:&str) -> bool {
+    let quoted = punc.chars().last().map(|c| punctuation_class(c) == PunctuationClass::Quote).unwrap_or(false);
+    !quoted && !punc.contains("..") && punc.chars().any(|c| c == '.' || c == '!' || c == '?')
+}
 
 pub fn as_string(c:char) -> String {
     let mut st = String::new();
@@ -44,17 +215,70 @@ pub fn as_string(c:char) -> String {
 
 enum ParseState {
     Word,
+    Ideograph,
+    Emoji,
+    Mention,
+    Hashtag,
+    Command,
     Punctuation,
-    Link,
+    // any other visible character, kept as punctuation of its own so "x@y" or a lone "€" survive
+    Symbol,
     Whitespace,
 }
 
+// number-like tokens are learned as their class, so "1999" and "2003" share statistics
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
+pub enum NumberKind {
+    Integer,
+    Decimal,
+    Year,
+    Time,
+    Currency,
+    Ordinal,
+    Percent,
+}
+
+impl NumberKind {
+    pub fn placeholder(&self) -> &'static str {
+        use self::NumberKind::*;
+        match *self {
+            Integer => "<NUM>",
+            Decimal => "<DEC>",
+            Year => "<YEAR>",
+            Time => "<TIME>",
+            Currency => "<MONEY>",
+            Ordinal => "<ORD>",
+            Percent => "<PCT>",
+        }
+    }
+
+    // used when the model has never seen a value of this kind
+    pub fn fallback(&self) -> &'static str {
+        use self::NumberKind::*;
+        match *self {
+            Integer => "3",
+            Decimal => "2.5",
+            Year => "2010",
+            Time => "5:30",
+            Currency => "$20",
+            Ordinal => "2nd",
+            Percent => "50%",
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Clone, Hash)]
 pub enum Token {
     Start,
     Word(String),
     Punctuation(String, bool),
-    Link(String),
+    Link(String, String), // the url and its domain
+    Emoji(String),
+    Mention(String), // without the @
+    Hashtag(String), // without the #
+    Command(String), // with the /, e.g. /gen_robe
+    Number(NumberKind, String), // empty once normalized for learning
+    Newline,
     End,
 }
 
@@ -65,7 +289,13 @@ impl fmt::Display for Token {
             &Start => write!(f, ".Start"),
             &Word(ref word) => write!(f, "{}", word),
             &Punctuation(ref punc, ref whitespace) => write!(f, "{}.sp?{}", punc, whitespace),
-            &Link(ref link) => write!(f, "{}", link),
+            &Link(ref link, _) => write!(f, "{}", link),
+            &Emoji(ref emoji) => write!(f, "{}", emoji),
+            &Mention(ref name) => write!(f, "@{}", name),
+            &Hashtag(ref tag) => write!(f, "#{}", tag),
+            &Command(ref command) => write!(f, "{}", command),
+            &Number(kind, ref value) => if value.is_empty() { write!(f, "{}", kind.placeholder()) } else { write!(f, "{}", value) },
+            &Newline => write!(f, ".Newline"),
             &End => write!(f, ".End"),
         }
     }
@@ -78,12 +308,100 @@ impl fmt::Debug for Token {
             &Start => write!(f, ".Start"),
             &Word(ref word) => write!(f, "{}", word),
             &Punctuation(ref punc, ref whitespace) => write!(f, "{}.sp?{}", punc, whitespace),
-            &Link(ref link) => write!(f, "{}", link),
+            &Link(ref link, _) => write!(f, "{}", link),
+            &Emoji(ref emoji) => write!(f, "{}", emoji),
+            &Mention(ref name) => write!(f, "@{}", name),
+            &Hashtag(ref tag) => write!(f, "#{}", tag),
+            &Command(ref command) => write!(f, "{}", command),
+            &Number(kind, ref value) => if value.is_empty() { write!(f, "{}", kind.placeholder()) } else { write!(f, "{}", value) },
+            &Newline => write!(f, ".Newline"),
             &End => write!(f, ".End"),
         }
     }
 }
 
+// numbers lose their value for learning, it gets filled back in at generation time
+pub fn normalize_token(token:&Token) -> Token {
+    match token {
+        &Token::Number(kind, _) => Token::Number(kind, String::new()),
+        _ => token.clone(),
+    }
+}
+
+// general category Sc from the unicode 14.0.0 character database, "$", "€", "₹", "₽" and the rest
+pub fn is_currency_sign(c:char) -> bool {
+    match c {
+        '$' | '\u{A2}'...'\u{A5}' | '\u{58F}' | '\u{60B}' | '\u{7FE}'...'\u{7FF}' | '\u{9F2}'...'\u{9F3}' | '\u{9FB}' |
+        '\u{AF1}' | '\u{BF9}' | '\u{E3F}' | '\u{17DB}' | '\u{20A0}'...'\u{20C0}' | '\u{A838}' | '\u{FDFC}' | '\u{FE69}' |
+        '\u{FF04}' | '\u{FFE0}'...'\u{FFE1}' | '\u{FFE5}'...'\u{FFE6}' | '\u{11FDD}'...'\u{11FE0}' | '\u{1E2FF}' | '\u{1ECB0}' => true,
+        _ => false,
+    }
+}
+
+const ORDINAL_SUFFIXES : [&'static str; 4] = ["st", "nd", "rd", "th"];
+
+// byte length and kind of the number at the start of text, "$20", "3:45pm", "2nd", "1999", "12.5%"
+pub fn number_length(text:&str) -> Option<(usize, NumberKind)> {
+    use self::NumberKind::*;
+
+    // a number never runs past whitespace, so that's as far as it needs to look
+    let chars : Vec<(usize, char)> = text.char_indices().take_while(|&(_, c)| !c.is_whitespace()).collect();
+    let n = chars.len();
+    let digit_at = |i:usize| i < n && chars[i].1.is_digit(10);
+    let digits_from = |mut i:usize| { while digit_at(i) { i += 1; } i };
+
+    let currency = n > 0 && is_currency_sign(chars[0].1);
+    let start = if currency { 1 } else { 0 };
+    let mut i = digits_from(start);
+    if i == start {
+        return None
+    }
+    let integer_digits = i - start;
+    let mut kind = if currency { Currency } else { Integer };
+
+    // thousands grouping, 1,000,000
+    while i + 3 < n + 1 && chars[i].1 == ',' && digits_from(i + 1) == i + 4 {
+        i += 4;
+    }
+    if i + 1 < n && chars[i].1 == '.' && digit_at(i + 1) {
+        i = digits_from(i + 1);
+        if !currency {
+            kind = Decimal;
+        }
+    } else if !currency && kind == Integer && i + 2 < n && chars[i].1 == ':' && digits_from(i + 1) == i + 3 {
+        i += 3;
+        if i + 2 < n && chars[i].1 == ':' && digits_from(i + 1) == i + 3 {
+            i += 3;
+        }
+        kind = Time;
+    }
+
+    let suffix : String = chars[i..].iter().take(2).map(|&(_, c)| c).collect::<String>().to_lowercase();
+    if (kind == Integer || kind == Time) && (suffix == "am" || suffix == "pm") {
+        i += 2;
+        kind = Time;
+    } else if kind == Integer && ORDINAL_SUFFIXES.contains(&suffix.as_str()) {
+        i += 2;
+        kind = Ordinal;
+    } else if (kind == Integer || kind == Decimal) && i < n && chars[i].1 == '%' {
+        i += 1;
+        kind = Percent;
+    }
+
+    if i < n && continues_word(chars[i].1) {
+        return None // 1d20, mp3 style words
+    }
+    if kind == Integer && integer_digits == 4 && i == start + 4 {
+        let value : u32 = text[..chars[i - 1].0 + 1].parse().unwrap_or(0);
+        if value >= 1900 && value < 2100 {
+            kind = Year;
+        }
+    }
+
+    let end = if i < n { chars[i].0 } else { chars[n - 1].0 + chars[n - 1].1.len_utf8() };
+    Some((end, kind))
+}
+
 pub fn is_punctuation(token:&Token) -> bool {
     match token {
         &Token::Punctuation(_, _) => true,
@@ -91,34 +409,76 @@ pub fn is_punctuation(token:&Token) -> bool {
     }
 }
 
-fn will_consume_char(st:&ParseState, c:char) -> bool {
+fn will_consume_char(st:&ParseState, token:&str, c:char) -> bool {
     use self::ParseState::*;
     match st {
-        &Word => CONTINUE_WORD.contains(&c),
+        &Word => continues_word(c),
+        &Ideograph => false,
+        &Emoji => continues_emoji(token, c),
+        &Mention | &Hashtag => continues_word(c),
+        &Command => continues_word(c) || c == '@', // /gen@robbot
         &Punctuation => PUNCTUATION.contains(&c),
-        &Link => c != ' ',
-        &Whitespace => c == ' ',
+        &Symbol => false,
+        &Whitespace => c.is_whitespace(),
     }
 }
 
 fn create_token(st:&ParseState, string:&str, trailing_whitespace: bool) -> Option<Token> {
     use self::ParseState::*;
     match st {
-        &Word => Some(Token::Word(String::from(string))),
-        &Punctuation => Some(Token::Punctuation(String::from(string), trailing_whitespace)),
-        &Link => Some(Token::Link(String::from(string))),
+        &Word | &Ideograph => Some(Token::Word(String::from(string))),
+        &Emoji => Some(Token::Emoji(String::from(string))),
+        // a sigil on its own isn't a mention/tag/command
+        &Mention | &Hashtag | &Command if string.chars().count() < 2 => Some(Token::Punctuation(String::from(string), trailing_whitespace)),
+        &Mention => Some(Token::Mention(String::from(&string[1..]))),
+        &Hashtag => Some(Token::Hashtag(String::from(&string[1..]))),
+        &Command => Some(Token::Command(String::from(string))),
+        &Punctuation | &Symbol => Some(Token::Punctuation(String::from(string), trailing_whitespace)),
         &Whitespace => None,
     }
 }
 
-fn new_state_for_char(c:&char) -> ParseState {
+// @, # and / only start a mention, tag or command at the beginning of a word, so emails and "yes/no" are left alone
+fn at_word_boundary(previous:Option<char>) -> bool {
+    match previous {
+        None => true,
+        Some(p) => p.is_whitespace() || (PUNCTUATION.contains(&p) && punctuation_class(p) != PunctuationClass::Closing && punctuation_class(p) != PunctuationClass::Joining),
+    }
+}
+
+fn new_state_for_char(c:&char, previous:Option<char>) -> ParseState {
     use self::ParseState::*;
-    if START_WORD.contains(c) {
+    let boundary = at_word_boundary(previous);
+    if boundary && *c == '@' {
+        Mention
+    } else if boundary && *c == '#' {
+        Hashtag
+    } else if boundary && *c == '/' {
+        Command
+    } else if starts_word(*c) {
         Word
+    } else if is_ideograph(*c) {
+        Ideograph
+    } else if is_emoji(*c) {
+        Emoji
     } else if PUNCTUATION.contains(c) {
         Punctuation
-    } else {
+    } else if c.is_whitespace() || c.is_control() {
         Whitespace
+    } else {
+        Symbol
+    }
+}
+
+// links and numbers need to see ahead of the current char, they're checked at the start of each word
+fn lookahead_token(text:&str) -> Option<(usize, Token)> {
+    if let Some(length) = link_length(text) {
+        let link = &text[..length];
+        Some((length, Token::Link(String::from(link), link_domain(link))))
+    } else if let Some((length, kind)) = number_length(text) {
+        Some((length, Token::Number(kind, String::from(&text[..length]))))
+    } else {
+        None
     }
 }
 
@@ -127,35 +487,59 @@ pub fn tokenize_line(line: &str) -> Vec<Token> {
     let mut tokens : Vec<Token> = Vec::new();
 
     let mut token = String::new();
+    let mut previous : Option<char> = None;
+    let mut skip_until : usize = 0; // end of a link already taken
 
     tokens.push(Token::Start);
 
-    for c in line.chars() {
-        let consume = will_consume_char(&parse_state, c);
+    for (at, c) in line.char_indices() {
+        if at < skip_until {
+            previous = Some(c);
+            continue;
+        }
+
+        if c == '\n' {
+            if let Some(pending) = create_token(&parse_state, &token, true) {
+                tokens.push(pending);
+            }
+            token.clear();
+            parse_state = ParseState::Whitespace;
+
+            tokens.push(Token::Newline);
+            previous = Some(c);
+            continue;
+        }
+
+        if at_word_boundary(previous) {
+            if let Some((length, new_token)) = lookahead_token(&line[at..]) {
+                if let Some(pending) = create_token(&parse_state, &token, false) {
+                    tokens.push(pending);
+                }
+                token.clear();
+                parse_state = ParseState::Whitespace;
+
+                tokens.push(new_token);
+                skip_until = at + length;
+                previous = Some(c);
+                continue;
+            }
+        }
+
+        let consume = will_consume_char(&parse_state, &token, c);
         if consume {
             token.push(c);
         } else {
-            let is_whitespace = c == ' ';
-            let evaluate_new_parser : bool = if !token.is_empty() {
-                if LINK_WORDS.contains(&token) { // it's a link, keep the state
-                    parse_state = ParseState::Link;
-                    false
-                } else if let Some(new_token) = create_token(&parse_state, &token, is_whitespace) {
+            let is_whitespace = c.is_whitespace();
+            if !token.is_empty() {
+                if let Some(new_token) = create_token(&parse_state, &token, is_whitespace) {
                     tokens.push(new_token);
-                    token.clear();
-                    true
-                } else {
-                    token.clear(); // like whitespace doesnt emit a token
-                    true
                 }
-            } else {
-                true
-            };
-            if evaluate_new_parser {
-                parse_state = new_state_for_char(&c);
+                token.clear(); // like whitespace doesnt emit a token
             }
+            parse_state = new_state_for_char(&c, previous);
             token.push(c);
         }
+        previous = Some(c);
     }
 
     if let Some(token) = create_token(&parse_state, &token, true) { // end of sentence is more whitespacey than not
@@ -165,4 +549,4 @@ pub fn tokenize_line(line: &str) -> Vec<Token> {
     tokens.push(Token::End);
 
     tokens
-}
\ No newline at end of file
+}
@@ -0,0 +1,41 @@
+use chrono::*;
+use rustc_serialize::json::{Json, ToJson};
+
+use std::collections::BTreeMap;
+use std::fs::*;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+// generation traces are written as one json object per line, for offline analysis
+
+lazy_static! {
+    static ref TRACE_SINK : Mutex<Option<File>> = Mutex::new(None);
+}
+
+pub fn open_trace(path:&Path) -> io::Result<()> {
+    let file = try!(OpenOptions::new().create(true).append(true).open(path));
+    let mut sink = TRACE_SINK.lock().unwrap();
+    *sink = Some(file);
+    Ok(())
+}
+
+pub fn tracing() -> bool {
+    TRACE_SINK.lock().unwrap().is_some()
+}
+
+pub fn record(kind:&str, payload:Json) {
+    let mut sink = TRACE_SINK.lock().unwrap();
+    if let Some(ref mut file) = *sink {
+        let mut object = BTreeMap::new();
+        object.insert(String::from("kind"), kind.to_json());
+        object.insert(String::from("at"), Local::now().to_rfc3339().to_json());
+        object.insert(String::from("data"), payload);
+
+        let line = format!("{}\n", Json::Object(object));
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            warn!("couldn't write trace record -> {:?}", e);
+        }
+    }
+}
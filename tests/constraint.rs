@@ -0,0 +1,31 @@
+extern crate robbot;
+
+use robbot::constraint::{Constraints, rhyme_key, rhymes_with, parse_constraints};
+
+#[test]
+fn rhymes_go_by_the_last_vowel_sound() {
+    for &(word, key) in &[("night", "ight"), ("cat", "at"), ("make", "ake"), ("time", "ime"), ("me", "e"), ("go", "o"), ("free", "ee"), ("rhythm", "ythm")] {
+        assert_eq!(rhyme_key(word), key, "{}", word);
+    }
+    for &(a, b) in &[("me", "be"), ("go", "no"), ("make", "cake"), ("night", "light"), ("some", "drum"), ("love", "glove")] {
+        assert!(rhymes_with(a, b), "{} and {} should rhyme", a, b);
+    }
+    for &(a, b) in &[("make", "like"), ("me", "time"), ("home", "some"), ("love", "move"), ("fire", "here"), ("cat", "cat")] {
+        assert!(!rhymes_with(a, b), "{} and {} shouldn't rhyme", a, b);
+    }
+}
+
+#[test]
+fn constraints_parse_from_their_arguments() {
+    assert_eq!(parse_constraints("with:Pizza with:cheese rhyme:cat words:8"), Ok(Constraints {
+        required: vec![String::from("pizza"), String::from("cheese")],
+        rhyme: Some(String::from("cat")),
+        words: Some(8),
+    }));
+    assert_eq!(parse_constraints(""), Ok(Constraints::default()));
+    assert!(parse_constraints("words:0").is_err());
+    assert!(parse_constraints("words:25").is_err());
+    assert!(parse_constraints("words:many").is_err());
+    assert!(parse_constraints("with:").is_err());
+    assert!(parse_constraints("pizza").is_err());
+}
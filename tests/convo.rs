@@ -0,0 +1,70 @@
+extern crate robbot;
+
+use std::env;
+use std::fs::*;
+use std::io::Write;
+use std::process;
+
+use robbot::seeded_rng;
+use robbot::model::{Model, create_models};
+use robbot::command::all_user_ids;
+use robbot::convo::{ConvoRequest, TurnTaking, DEFAULT_CONVO_LINES, parse_convo, next_speaker};
+
+const MICHAEL : u64 = 101710896;
+const ROBE : u64 = 99688863;
+const MIKEL : u64 = 91597707;
+
+// robe is only ever answered by mikel, and michael never talks
+fn chat_model() -> Model {
+    let dir = env::temp_dir().join(format!("robbot-convo-{}", process::id()));
+    let _ = remove_dir_all(&dir);
+    create_dir_all(&dir).unwrap();
+    let log = dir.join("2016-03-12.log");
+    let mut file = File::create(&log).unwrap();
+    for &(user_id, text) in &[(ROBE, "pizza?"), (MIKEL, "pizza!"), (ROBE, "when"), (MIKEL, "now"), (ROBE, "ok")] {
+        writeln!(file, "{} {}", user_id, text).unwrap();
+    }
+    create_models(vec![log])
+}
+
+#[test]
+fn convos_name_their_speakers_once() {
+    assert_eq!(parse_convo("robe mikel robe 8 turns"), Ok(ConvoRequest {
+        speakers: vec![ROBE, MIKEL],
+        lines: 8,
+        turn_taking: TurnTaking::Learned,
+    }));
+    let everyone = parse_convo("").unwrap();
+    assert_eq!(everyone.speakers.len(), all_user_ids().len());
+    assert_eq!(everyone.lines, DEFAULT_CONVO_LINES);
+    assert_eq!(parse_convo("turns").unwrap().speakers, everyone.speakers);
+}
+
+#[test]
+fn convos_need_two_people_and_a_sensible_length() {
+    for args in &["robe", "robe turns", "robe robe", "robe robe turns", "robe nobody", "robe mikel 0", "robe mikel 13"] {
+        assert!(parse_convo(args).is_err(), "{:?}", args);
+    }
+}
+
+#[test]
+fn round_robin_takes_turns_in_order() {
+    let model = chat_model();
+    let request = parse_convo("mikel robe michael").unwrap();
+    let mut rng = seeded_rng(1);
+    let order : Vec<u64> = (0..5).map(|turn| next_speaker(&model, &request, turn, None, &mut rng)).collect();
+    assert_eq!(order, vec![MIKEL, ROBE, MICHAEL, MIKEL, ROBE]);
+}
+
+#[test]
+fn learned_turns_follow_who_answers_whom() {
+    let model = chat_model();
+    let request = parse_convo("robe mikel michael turns").unwrap();
+    let mut rng = seeded_rng(1);
+    for turn in 0..20 {
+        assert_eq!(next_speaker(&model, &request, turn, Some(ROBE), &mut rng), MIKEL);
+        // nobody ever answered michael, so anyone else can
+        assert!(next_speaker(&model, &request, turn, Some(MICHAEL), &mut rng) != MICHAEL);
+        assert!(request.speakers.contains(&next_speaker(&model, &request, turn, None, &mut rng)));
+    }
+}
@@ -0,0 +1,135 @@
+extern crate robbot;
+
+use std::env;
+use std::fs::*;
+use std::io::Write;
+use std::process;
+
+use robbot::seeded_rng;
+use robbot::model::{Model, create_models};
+use robbot::tokenizer::{Token, tokenize_line, normalize_token};
+use robbot::generate::{Candidate, GenerationConfig, Filtered, Unsteered, generate_novel, generate_best, resolve_start};
+
+const ROBE : u64 = 99688863;
+const MIKEL : u64 = 91597707;
+
+// a model learned from a single log of robe saying the given lines
+fn corpus_model(name:&str, lines:&[&str]) -> Model {
+    let dir = env::temp_dir().join(format!("robbot-generate-{}-{}", name, process::id()));
+    let _ = remove_dir_all(&dir);
+    create_dir_all(&dir).unwrap();
+    let log = dir.join("2016-03-12.log");
+    let mut file = File::create(&log).unwrap();
+    for line in lines {
+        writeln!(file, "{} {}", ROBE, line).unwrap();
+    }
+    // someone else's words, so there's a vocabulary beyond robe's own
+    writeln!(file, "{} the skateboarding pizzas", MIKEL).unwrap();
+    create_models(vec![log])
+}
+
+fn line_indices(model:&Model, text:&str) -> Vec<usize> {
+    let line = tokenize_line(text).iter().map(normalize_token).collect();
+    model.line_indices(&line)
+}
+
+#[test]
+fn copied_lines_are_not_novel() {
+    let model = corpus_model("novelty", &[
+        "the quick brown fox jumps over the lazy dog today",
+        "my cat sleeps on the warm mat all day long",
+    ]);
+    let lines = &model.users[&ROBE].own_lines;
+
+    assert!(!lines.is_novel(&line_indices(&model, "my cat sleeps on the warm mat all day long"), 1));
+    // a long enough run of a real line is a copy too
+    assert!(!lines.is_novel(&line_indices(&model, "quick brown fox jumps over the lazy dog"), 1));
+    assert!(lines.is_novel(&line_indices(&model, "the quick brown fox sleeps on the warm mat"), 1));
+    // unless the run is the start someone asked for
+    let copied_start = line_indices(&model, "my cat sleeps on the warm mat");
+    assert!(lines.is_novel(&copied_start, copied_start.len()));
+}
+
+fn candidate(text:&str, log_likelihood:f64, branching:usize) -> Candidate {
+    Candidate {
+        line: tokenize_line(text),
+        start_len: 1,
+        log_likelihood: log_likelihood,
+        branching: branching,
+    }
+}
+
+#[test]
+fn longer_likelier_finished_lines_score_higher() {
+    let config = GenerationConfig::default();
+    let finished = candidate("the cat sat on the mat", -3.0, 2);
+    let mut cut_off = finished.clone();
+    cut_off.line.pop();
+    assert!(finished.score(&config) > cut_off.score(&config));
+    assert!(finished.score(&config) > candidate("the cat", -3.0, 2).score(&config));
+    assert!(finished.score(&config) > candidate("the cat sat on the mat", -12.0, 2).score(&config));
+
+    let nothing = Candidate { line: vec![], start_len: 0, log_likelihood: 0.0, branching: 0 };
+    assert_eq!(nothing.score(&config), ::std::f64::NEG_INFINITY);
+}
+
+#[test]
+fn the_best_scoring_candidate_wins() {
+    let model = corpus_model("best", &[
+        "the cat sat on the mat",
+        "the dog sat on the log and barked",
+        "a cat ran off the mat",
+        "the dog ran to the cat",
+        "a bird sat on the dog",
+    ]);
+    let user_model = &model.users[&ROBE];
+    let start = vec![Token::Start];
+    let config = GenerationConfig { best_of: 6, ..GenerationConfig::default() };
+
+    // the same seed makes the same candidates generate_best picks from
+    let mut rng = seeded_rng(7);
+    let steering = Filtered { config: &config, inner: &Unsteered };
+    let scores : Vec<f64> = (0..config.best_of)
+        .filter_map(|_| generate_novel(&model, &mut rng, &start, user_model, &config, &steering))
+        .map(|c| c.score(&config))
+        .collect();
+    assert!(scores.len() > 1);
+    let best_score = scores.iter().cloned().fold(::std::f64::NEG_INFINITY, f64::max);
+    assert!(scores.iter().any(|&s| s < best_score));
+
+    let best = generate_best(&model, &mut seeded_rng(7), &start, user_model, &config, &Unsteered).unwrap();
+    assert_eq!(best.score(&config), best_score);
+}
+
+fn start(text:&str) -> Vec<Token> {
+    let mut tokens = tokenize_line(text);
+    tokens.pop();
+    tokens
+}
+
+#[test]
+fn misspelled_starts_go_with_the_closest_word() {
+    let model = corpus_model("resolve", &["the pizza was great", "boarding the train"]);
+    let user_model = &model.users[&ROBE];
+
+    let resolved = resolve_start(&model, user_model, &start("the piza")).unwrap();
+    assert_eq!(resolved.context, start("the pizza"));
+    assert_eq!(resolved.substitutions, vec![(String::from("piza"), String::from("pizza"))]);
+    assert_eq!(resolved.original, start("the piza"));
+
+    // close to a word of robe's and a word of mikel's, robe's own wins
+    let resolved = resolve_start(&model, user_model, &start("pizzaz")).unwrap();
+    assert_eq!(resolved.context, start("pizza"));
+    // nothing within a typo, but the same ending
+    let resolved = resolve_start(&model, user_model, &start("snowboarding")).unwrap();
+    assert_eq!(resolved.context, start("boarding"));
+    assert!(resolve_start(&model, user_model, &start("the pizza")).unwrap().substitutions.is_empty());
+}
+
+#[test]
+fn starts_with_nothing_close_are_refused() {
+    let model = corpus_model("unresolved", &["the pizza was great"]);
+    let user_model = &model.users[&ROBE];
+    assert!(resolve_start(&model, user_model, &start("xylophone")).is_err());
+    assert!(resolve_start(&model, user_model, &start("the pizza xylophone")).is_err());
+}
@@ -0,0 +1,179 @@
+extern crate robbot;
+extern crate chrono;
+
+use std::env;
+use std::fs::*;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use chrono::NaiveDate;
+
+use robbot::migrate::{migrate_root, verify, timestamp_for_date};
+use robbot::persistence::{LogEntry, parse_log_line};
+use robbot::index::INDEX_FILE;
+
+const MICHAEL : u64 = 101710896;
+const ROBE : u64 = 99688863;
+
+// a chat root as the old bot left it, a day's group log and the imported history, each with a bit of everything
+fn legacy_root(name:&str) -> PathBuf {
+    let root = env::temp_dir().join(format!("robbot-migrate-{}-{}", name, process::id()));
+    let _ = remove_dir_all(&root);
+    create_dir_all(root.join("100")).unwrap();
+
+    let mut json = LogEntry::new(MICHAEL, "already json");
+    json.timestamp = Some(1457800000);
+    write_file(&root.join("100").join("2016-03-12.log"), &format!(
+        "{} two\\nlines\n{}not a message\n{} back\\\\slash\n", ROBE, json.to_log_line(), MICHAEL));
+    write_file(&root.join("history.log"), &format!("{} from before the bot\n{} \"quoted\" & <tagged>\n", ROBE, MICHAEL));
+    write_file(&root.join(INDEX_FILE), "robbot-index 2\n");
+    root
+}
+
+fn write_file(path:&Path, contents:&str) {
+    File::create(path).unwrap().write_all(contents.as_bytes()).unwrap();
+}
+
+fn read_file(path:&Path) -> String {
+    let mut contents = String::new();
+    File::open(path).unwrap().read_to_string(&mut contents).unwrap();
+    contents
+}
+
+fn backup(path:&Path) -> PathBuf {
+    path.with_extension("log.bak")
+}
+
+// as if the bot had escaped line breaks since before any of these logs
+fn escaped_since() -> Option<NaiveDate> {
+    NaiveDate::from_ymd_opt(2016, 1, 1)
+}
+
+#[test]
+fn legacy_lines_become_json_and_the_rest_is_left_alone() {
+    let root = legacy_root("convert");
+    let day = root.join("100").join("2016-03-12.log");
+    let history = root.join("history.log");
+    let original_day = read_file(&day);
+    let original_history = read_file(&history);
+
+    let report = migrate_root(&root, false, escaped_since()).unwrap();
+    assert_eq!(report.migrated.len(), 2);
+    assert!(report.skipped.is_empty());
+    assert!(report.refused.is_empty());
+    let &(_, ref day_report) = report.migrated.iter().find(|&&(ref path, _)| path == &day).unwrap();
+    assert_eq!((day_report.lines, day_report.converted, day_report.unreadable), (4, 2, 1));
+
+    let lines : Vec<String> = read_file(&day).lines().map(String::from).collect();
+    let original_lines : Vec<&str> = original_day.lines().collect();
+    assert_eq!(lines.len(), 4);
+    let midnight = Some(timestamp_for_date(NaiveDate::from_ymd(2016, 3, 12)));
+
+    let first = parse_log_line(&lines[0]).unwrap();
+    assert!(lines[0].starts_with('{'));
+    assert_eq!((first.user_id, first.text.as_str(), first.timestamp), (ROBE, "two\nlines", midnight));
+    assert_eq!(lines[1], original_lines[1]);
+    assert_eq!(lines[2], "not a message");
+    let last = parse_log_line(&lines[3]).unwrap();
+    assert_eq!((last.user_id, last.text.as_str(), last.timestamp), (MICHAEL, "back\\slash", midnight));
+
+    // history.log isn't named for a day, so its messages stay undated
+    let imported : Vec<LogEntry> = read_file(&history).lines().map(|l| parse_log_line(l).unwrap()).collect();
+    assert_eq!(imported[1].text, "\"quoted\" & <tagged>");
+    assert!(imported.iter().all(|entry| entry.timestamp.is_none()));
+
+    assert_eq!(read_file(&backup(&day)), original_day);
+    assert_eq!(read_file(&backup(&history)), original_history);
+    assert!(!day.with_extension("log.migrating").exists());
+    assert!(!root.join(INDEX_FILE).exists());
+}
+
+#[test]
+fn logs_from_before_escaping_are_taken_as_typed() {
+    let root = legacy_root("unescaped");
+    let day = root.join("100").join("2016-03-12.log");
+    migrate_root(&root, false, NaiveDate::from_ymd_opt(2017, 1, 2)).unwrap();
+
+    let texts : Vec<String> = read_file(&day).lines().filter_map(parse_log_line).map(|entry| entry.text).collect();
+    assert_eq!(texts, vec!["two\\nlines", "already json", "back\\\\slash"]);
+}
+
+#[test]
+fn lines_that_could_be_escaped_or_not_are_refused() {
+    let root = legacy_root("ambiguous");
+    let day = root.join("100").join("2016-03-12.log");
+    let original_day = read_file(&day);
+
+    let report = migrate_root(&root, false, None).unwrap();
+    assert_eq!(report.refused, vec![(day.clone(), vec![1, 4])]);
+    // nothing else in the day's log needed converting, so it's left alone
+    assert_eq!(read_file(&day), original_day);
+    assert!(!backup(&day).exists());
+    assert_eq!(report.migrated.len(), 1);
+
+    let report = migrate_root(&root, false, None).unwrap();
+    assert_eq!(report.refused.len(), 1);
+    assert!(report.migrated.is_empty());
+    assert_eq!(read_file(&day), original_day);
+}
+
+#[test]
+fn a_second_run_has_nothing_to_do() {
+    let root = legacy_root("again");
+    let day = root.join("100").join("2016-03-12.log");
+    migrate_root(&root, false, escaped_since()).unwrap();
+    let migrated = read_file(&day);
+    let backed_up = read_file(&backup(&day));
+    write_file(&root.join(INDEX_FILE), "robbot-index 2\n");
+
+    let report = migrate_root(&root, false, escaped_since()).unwrap();
+    assert!(report.migrated.is_empty());
+    assert_eq!(report.skipped.len(), 2);
+    assert_eq!(read_file(&day), migrated);
+    assert_eq!(read_file(&backup(&day)), backed_up);
+    // nothing was rewritten, so the index is still good
+    assert!(root.join(INDEX_FILE).exists());
+}
+
+#[test]
+fn a_dry_run_changes_nothing() {
+    let root = legacy_root("dry");
+    let day = root.join("100").join("2016-03-12.log");
+    let original = read_file(&day);
+
+    let report = migrate_root(&root, true, escaped_since()).unwrap();
+    assert_eq!(report.migrated.len(), 2);
+    assert_eq!(read_file(&day), original);
+    assert!(!backup(&day).exists());
+    assert!(root.join(INDEX_FILE).exists());
+}
+
+#[test]
+fn a_conversion_that_changes_a_message_is_refused() {
+    let root = legacy_root("verify");
+    let original : Vec<String> = vec![format!("{} pizza", ROBE), String::from("not a message")];
+    let converted = root.join("converted.log");
+
+    write_file(&converted, &format!("{}not a message\n", LogEntry::new(ROBE, "pizza").to_log_line()));
+    assert!(verify(&original, &converted, None).is_ok());
+
+    write_file(&converted, &format!("{}not a message\n", LogEntry::new(ROBE, "pineapple").to_log_line()));
+    assert!(verify(&original, &converted, None).is_err());
+    write_file(&converted, &format!("{}not a message\n", LogEntry::new(MICHAEL, "pizza").to_log_line()));
+    assert!(verify(&original, &converted, None).is_err());
+    write_file(&converted, &LogEntry::new(ROBE, "pizza").to_log_line());
+    assert!(verify(&original, &converted, None).is_err());
+    write_file(&converted, &format!("{}a different non message\n", LogEntry::new(ROBE, "pizza").to_log_line()));
+    assert!(verify(&original, &converted, None).is_err());
+
+    // escapes are checked against what was actually stored
+    let original = vec![format!("{} two\\nlines", ROBE)];
+    write_file(&converted, &LogEntry::new(ROBE, "two\nlines").to_log_line());
+    assert!(verify(&original, &converted, Some(true)).is_ok());
+    assert!(verify(&original, &converted, Some(false)).is_err());
+    assert!(verify(&original, &converted, None).is_err());
+    write_file(&converted, &LogEntry::new(ROBE, "two\\nlines").to_log_line());
+    assert!(verify(&original, &converted, Some(false)).is_ok());
+    assert!(verify(&original, &converted, Some(true)).is_err());
+}
@@ -0,0 +1,19 @@
+extern crate robbot;
+
+use robbot::syllable::count_syllables;
+
+#[test]
+fn syllables_are_counted_like_they_are_said() {
+    let words : &[(&str, usize)] = &[
+        ("cat", 1), ("make", 1), ("the", 1), ("me", 1), ("great", 1), ("sea", 1), ("toe", 1), ("goes", 1),
+        ("jumped", 1), ("makes", 1), ("whale", 1), ("table", 2), ("people", 2), ("horses", 2),
+        ("idea", 3), ("area", 3), ("poem", 2), ("poet", 2), ("create", 2), ("radio", 3), ("video", 3), ("neon", 2),
+        ("trio", 2), ("via", 2), ("nation", 2), ("region", 2), ("precious", 2), ("special", 2), ("quality", 3),
+        ("pigeon", 2), ("geology", 4), ("ocean", 2), ("beautiful", 3),
+        ("business", 2), ("kindness", 2), ("lateness", 2), ("happiness", 3),
+        ("Yesterday", 3), ("", 0), ("hmm", 1),
+    ];
+    for &(word, syllables) in words {
+        assert_eq!(count_syllables(word), syllables, "{}", word);
+    }
+}
@@ -0,0 +1,207 @@
+extern crate robbot;
+
+use robbot::tokenizer::{tokenize_line, normalize_token, Token, NumberKind};
+use robbot::generate::generate_sentence;
+use robbot::command::take_seed;
+use robbot::persistence::{LogEntry, parse_log_line};
+
+// typical chat lines that should come back out of the detokenizer exactly as they went in
+const CORPUS : &'static [&'static str] = &[
+    "Hello, world.",
+    "I think so (maybe).",
+    "She said \"no way\" and left.",
+    "Well - I don't know.",
+    "It's a well-known fact.",
+    "Meet at 3:45 tomorrow?",
+    "Check http://example.com/foo now!",
+    "Yes/no?",
+    "Wow!! That's great.",
+    "Ok... sure.",
+    "Pick one [a, b or c].",
+    "Tom & Jerry, AT&T.",
+    "\"Really?\" he asked.",
+    "I'm fine; you?",
+    "Is this 'quoted' text?",
+    "That was close. Really close!",
+    "Café crème, s'il vous plaît.",
+    "Привет, как дела?",
+    "Straße über Ärger.",
+    "日本語が好き。",
+    "Ça va? Très bien!",
+    "“Curly quotes” don’t break anything.",
+    "Lol 😂😂 nice one 👍🏽",
+    "🎉 Party time! 🇦🇺🇳🇿",
+    "Family 👨‍👩‍👧‍👦 photo.",
+    "Hey @robe, check #tbt (and /gen_robe).",
+    "/roll 1d20 please",
+    "Yes/no and and/or aren't commands.",
+    "Read this (https://x.y.com/z).",
+    "See example.com/foo, it's good.",
+    "Mail bob@example.com or mailto:amy@example.org!",
+    "Grab ftp://files.example.org/a.txt now.",
+    "https://en.wikipedia.org/wiki/Rust_(programming_language) is long.",
+    "It costs $20, or €5.50 at 3pm.",
+    "We came 2nd in 1999, 50% of the time.",
+    "Roll 1d20 and add 1,000.",
+    "Shopping list:\n- Eggs\n- Milk",
+    "First line.\nSecond line!",
+    "That's ₹5 or ₽300.",
+    "$",
+    "€",
+    "Write to x@y about it.",
+    "It was 100€ and 50¢.",
+];
+
+#[test]
+fn corpus_round_trips() {
+    for line in CORPUS {
+        let tokens = tokenize_line(line);
+        assert_eq!(generate_sentence(&tokens), *line, "tokens {:?}", tokens);
+    }
+}
+
+#[test]
+fn sentence_case_is_restored() {
+    let tokens = tokenize_line("what. i'm not sure, i think so? ok");
+    assert_eq!(generate_sentence(&tokens), "What. I'm not sure, I think so? Ok");
+}
+
+#[test]
+fn unfinished_lines_keep_their_last_token() {
+    let mut tokens = tokenize_line("hello there");
+    tokens.pop(); // no end
+    assert_eq!(generate_sentence(&tokens), "Hello there");
+}
+
+#[test]
+fn unicode_whitespace_separates_words() {
+    let tokens = tokenize_line("one\ttwo\u{A0}three\u{3000}four");
+    let words : Vec<Token> = vec!("one", "two", "three", "four").into_iter().map(|w| Token::Word(String::from(w))).collect();
+    assert_eq!(&tokens[1..tokens.len() - 1], &words[..]);
+}
+
+#[test]
+fn accented_and_cyrillic_words_are_kept_whole() {
+    let tokens = tokenize_line("naïve résumé привет");
+    assert_eq!(tokens.len(), 5);
+    assert_eq!(tokens[2], Token::Word(String::from("résumé")));
+}
+
+#[test]
+fn combining_marks_stay_in_their_word() {
+    let decomposed = "cafe\u{301} noir";
+    let tokens = tokenize_line(decomposed);
+    assert_eq!(tokens[1], Token::Word(String::from("cafe\u{301}")));
+}
+
+#[test]
+fn indic_viramas_and_nuktas_stay_in_their_word() {
+    for word in &["क्षमा", "ক্ষমা", "தமிழ்", "ਸ੍ਰੀ", "ਖ਼ਾਲਸਾ", "తెలుగు", "ಕನ್ನಡ", "മലയാളം"] {
+        let tokens = tokenize_line(word);
+        assert_eq!(&tokens[1..tokens.len() - 1], &[Token::Word(String::from(*word))], "{}", word);
+    }
+}
+
+#[test]
+fn emoji_sequences_are_single_tokens() {
+    let emoji = |e:&str| Token::Emoji(String::from(e));
+    let tokens = tokenize_line("👍🏽👨\u{200D}👩\u{200D}👧 🇦🇺🇳🇿 ❤\u{FE0F}");
+    assert_eq!(&tokens[1..tokens.len() - 1], &[emoji("👍🏽"), emoji("👨\u{200D}👩\u{200D}👧"), emoji("🇦🇺"), emoji("🇳🇿"), emoji("❤\u{FE0F}")]);
+}
+
+#[test]
+fn mentions_hashtags_and_commands_are_tokens() {
+    let tokens = tokenize_line("@robe #tbt /gen_robe@robbot yes/no");
+    assert_eq!(tokens[1], Token::Mention(String::from("robe")));
+    assert_eq!(tokens[2], Token::Hashtag(String::from("tbt")));
+    assert_eq!(tokens[3], Token::Command(String::from("/gen_robe@robbot")));
+    assert_eq!(tokens[5], Token::Punctuation(String::from("/"), false));
+}
+
+#[test]
+fn links_record_their_domain_and_drop_trailing_punctuation() {
+    let tokens = tokenize_line("(https://www.Example.com:8080/a?b=c). then example.co.uk/x, mailto:bob@mail.example.org");
+    assert_eq!(tokens[2], Token::Link(String::from("https://www.Example.com:8080/a?b=c"), String::from("example.com")));
+    assert_eq!(tokens[3], Token::Punctuation(String::from(")."), true));
+    assert_eq!(tokens[5], Token::Link(String::from("example.co.uk/x"), String::from("example.co.uk")));
+    assert_eq!(tokens[7], Token::Link(String::from("mailto:bob@mail.example.org"), String::from("mail.example.org")));
+}
+
+#[test]
+fn things_that_look_a_bit_like_links_are_not() {
+    for text in &["e.g. this", "node.js rocks", "3.5 stars", "http:// nothing", "end.So what"] {
+        let tokens = tokenize_line(text);
+        assert!(!tokens.iter().any(|t| match *t { Token::Link(_, _) => true, _ => false }), "{} -> {:?}", text, tokens);
+    }
+}
+
+#[test]
+fn numbers_are_classified_and_normalize_to_their_kind() {
+    let kinds : Vec<Option<NumberKind>> = tokenize_line("1999 3:45 3pm $20 €5.50 2nd 50% 1,000 2.5 7 1d20").iter().map(|t| match *t {
+        Token::Number(kind, _) => Some(kind),
+        _ => None,
+    }).collect();
+    assert_eq!(kinds, vec!(None, Some(NumberKind::Year), Some(NumberKind::Time), Some(NumberKind::Time), Some(NumberKind::Currency),
+        Some(NumberKind::Currency), Some(NumberKind::Ordinal), Some(NumberKind::Percent), Some(NumberKind::Integer),
+        Some(NumberKind::Decimal), Some(NumberKind::Integer), None, None));
+
+    let tokens = tokenize_line("1984 2001");
+    assert_eq!(normalize_token(&tokens[1]), normalize_token(&tokens[2]));
+    assert_eq!(normalize_token(&tokens[1]).to_string(), "<YEAR>");
+}
+
+#[test]
+fn any_currency_sign_starts_money() {
+    for text in &["₹5", "₽300", "₩1,000", "฿20.50"] {
+        assert_eq!(tokenize_line(text)[1], Token::Number(NumberKind::Currency, String::from(*text)));
+    }
+}
+
+#[test]
+fn unknown_symbols_are_kept_as_punctuation() {
+    let tokens = tokenize_line("x@y ~ $");
+    let word = |w:&str| Token::Word(String::from(w));
+    let symbol = |s:&str, whitespace:bool| Token::Punctuation(String::from(s), whitespace);
+    assert_eq!(tokens[1..].to_vec(), vec!(word("x"), symbol("@", false), word("y"), symbol("~", true), symbol("$", true), Token::End));
+}
+
+#[test]
+fn long_numbers_end_where_their_digits_do() {
+    let digits = "1".repeat(40);
+    let tokens = tokenize_line(&format!("{} hello world", digits));
+    let word = |w:&str| Token::Word(String::from(w));
+    assert_eq!(tokens[1..].to_vec(), vec!(Token::Number(NumberKind::Integer, digits.clone()), word("hello"), word("world"), Token::End));
+}
+
+#[test]
+fn log_entries_survive_the_log_format() {
+    let mut entry = LogEntry::new(99688863, "two\nlines with \"quotes\", a \\ and ünïcödé 🍕");
+    entry.timestamp = Some(1457000000);
+    entry.message_id = Some(12);
+    entry.reply_to = Some(11);
+    entry.username = Some(String::from("robe"));
+    entry.forward_from = Some(91597707);
+
+    let line = entry.to_log_line();
+    assert_eq!(line.matches('\n').count(), 1);
+    assert_eq!(parse_log_line(&line), Some(entry));
+
+    let bare = LogEntry::new(1, "plain");
+    assert_eq!(parse_log_line(&bare.to_log_line()), Some(bare));
+}
+
+#[test]
+fn legacy_log_lines_still_read() {
+    assert_eq!(parse_log_line("99688863 two\\nlines\n"), Some(LogEntry::new(99688863, "two\nlines")));
+    assert_eq!(parse_log_line("not a line"), None);
+    assert_eq!(parse_log_line("{\"v\":99,\"user\":1,\"text\":\"from the future\"}"), None);
+}
+
+#[test]
+fn taking_the_seed_leaves_the_rest_as_written() {
+    assert_eq!(take_seed("roses are red\nseed:42 violets  are\tblue"), Ok((Some(42), String::from("roses are red\nviolets  are\tblue"))));
+    assert_eq!(take_seed("seed:7 hello there"), Ok((Some(7), String::from("hello there"))));
+    assert_eq!(take_seed("hello seed:7"), Ok((Some(7), String::from("hello"))));
+    assert_eq!(take_seed("first\nsecond"), Ok((None, String::from("first\nsecond"))));
+    assert!(take_seed("seed:many").is_err());
+}
@@ -0,0 +1,404 @@
+extern crate robbot;
+extern crate chrono;
+
+use std::env;
+use std::fs::*;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use chrono::NaiveDate;
+
+use robbot::index::{SearchIndex, DocId, INDEX_FILE};
+use robbot::search::{SearchConfig, SortOrder, Phrase, parse_query, search_terms};
+use robbot::persistence::LogEntry;
+use robbot::html::{Html, MAX_MESSAGE_LENGTH};
+use robbot::grep::{grep, compile_pattern, GrepLimits};
+use robbot::command::all_user_ids;
+use robbot::bot::parse_grep;
+use robbot::stats::{count_by_user, count_by_month, sparkline};
+
+const MICHAEL : u64 = 101710896;
+const ROBE : u64 = 99688863;
+const MIKEL : u64 = 91597707;
+
+const GROUP : u64 = 100;
+
+// a fresh directory per test, so they can run side by side
+fn scratch_root(name:&str) -> PathBuf {
+    let root = env::temp_dir().join(format!("robbot-search-{}-{}", name, process::id()));
+    let _ = remove_dir_all(&root);
+    create_dir_all(root.join(GROUP.to_string())).unwrap();
+    root
+}
+
+// appends to the log the way the bot does, giving back the offset and length of the line
+fn append(path:&Path, entry:&LogEntry) -> (u64, u64) {
+    let offset = metadata(path).map(|m| m.len()).unwrap_or(0);
+    let line = entry.to_log_line();
+    let mut file = OpenOptions::new().create(true).append(true).open(path).unwrap();
+    file.write_all(line.as_bytes()).unwrap();
+    (offset, line.len() as u64)
+}
+
+fn group_log(root:&Path, date:&str) -> PathBuf {
+    root.join(GROUP.to_string()).join(format!("{}.log", date))
+}
+
+fn phrase(text:&str) -> Phrase {
+    search_terms(text)
+}
+
+// a small group history over two days
+fn sample_index(name:&str) -> SearchIndex {
+    let root = scratch_root(name);
+    let first = group_log(&root, "2016-03-12");
+    let second = group_log(&root, "2017-01-02");
+    for &(user_id, text) in &[
+        (ROBE, "deep dish pizza is the best pizza"),
+        (MICHAEL, "pizza with pineapple"),
+        (ROBE, "the dish was deep"),
+        (MIKEL, "cats and dogs"),
+        (MICHAEL, "a cat"),
+        (ROBE, "I ate at the restaurant"),
+    ] {
+        append(&first, &LogEntry::new(user_id, text));
+    }
+    append(&second, &LogEntry::new(ROBE, "pizza tonight"));
+    SearchIndex::open(&root, &vec![first, second]).unwrap()
+}
+
+// what /search would show, in order
+fn find(index:&SearchIndex, text:&str, asker:u64) -> Vec<DocId> {
+    let config = SearchConfig::default();
+    let mut query = parse_query(text, asker).unwrap();
+    index.expand(&mut query, &config);
+    let mut docs = index.search(&query);
+    index.rank(&mut docs, &query, &config);
+    docs
+}
+
+fn find_any_order(index:&SearchIndex, text:&str) -> Vec<DocId> {
+    let mut docs = find(index, text, MICHAEL);
+    docs.sort();
+    docs
+}
+
+#[test]
+fn index_picks_up_where_it_left_off() {
+    let root = scratch_root("reopen");
+    let log = group_log(&root, "2016-03-12");
+    append(&log, &LogEntry::new(ROBE, "deep dish pizza"));
+
+    let mut index = SearchIndex::open(&root, &vec![log.clone()]).unwrap();
+    assert_eq!(index.docs.len(), 1);
+    assert!(root.join(INDEX_FILE).is_file());
+
+    // stored while the bot is running
+    let entry = LogEntry::new(MICHAEL, "thin crust pizza");
+    let (offset, len) = append(&log, &entry);
+    index.add_message(&log, offset, len, &entry).unwrap();
+    // not written out with every message, only once it's been a while
+    index.save_if_due().unwrap();
+    assert!(index.has_unsaved());
+    index.save().unwrap();
+    assert!(!index.has_unsaved());
+    // and logged while it wasn't looking
+    append(&log, &LogEntry::new(ROBE, "pizza again"));
+
+    let index = SearchIndex::open(&root, &vec![log.clone()]).unwrap();
+    assert_eq!(index.docs.len(), 3);
+    assert_eq!(index.lookup(&phrase("pizza")), vec![0, 1, 2]);
+    assert_eq!(index.lookup(&phrase("thin crust")), vec![1]);
+    assert_eq!(index.message(2).unwrap().text, "pizza again");
+    assert_eq!(index.files[0].group, Some(GROUP));
+    assert_eq!(index.date(0), NaiveDate::from_ymd_opt(2016, 3, 12));
+}
+
+#[test]
+fn postings_to_missing_docs_rebuild_the_index() {
+    let root = scratch_root("corrupt");
+    let log = group_log(&root, "2016-03-12");
+    let (_, len) = append(&log, &LogEntry::new(ROBE, "pizza"));
+
+    let mut saved = File::create(root.join(INDEX_FILE)).unwrap();
+    write!(saved, "robbot-index 2\nfile {} {} {}\ndoc 0 0 {} 1 -\nterm pizza 0:1 7:1\n", len, GROUP, log.to_string_lossy(), ROBE).unwrap();
+
+    let index = SearchIndex::open(&root, &vec![log.clone()]).unwrap();
+    assert_eq!(index.lookup(&phrase("pizza")), vec![0]);
+}
+
+#[test]
+fn quoted_words_are_phrases() {
+    let query = parse_query("pizza \"deep dish\"", ROBE).unwrap();
+    assert_eq!(query.required, vec![vec![phrase("pizza")], vec![phrase("deep dish")]]);
+    assert!(query.has_phrases());
+    assert!(!parse_query("pizza dish", ROBE).unwrap().has_phrases());
+}
+
+#[test]
+fn minus_excludes_words_and_phrases() {
+    let query = parse_query("pizza -pineapple -\"deep dish\"", ROBE).unwrap();
+    assert_eq!(query.required, vec![vec![phrase("pizza")]]);
+    assert_eq!(query.excluded, vec![phrase("pineapple"), phrase("deep dish")]);
+}
+
+#[test]
+fn or_joins_neighbouring_words() {
+    let query = parse_query("cat OR dog fish", ROBE).unwrap();
+    assert_eq!(query.required, vec![vec![phrase("cat"), phrase("dog")], vec![phrase("fish")]]);
+    assert!(parse_query("OR cat", ROBE).is_err());
+    assert!(parse_query("cat OR", ROBE).is_err());
+}
+
+#[test]
+fn from_names_people() {
+    let query = parse_query("pizza from:robe from:me", MICHAEL).unwrap();
+    assert_eq!(query.users, vec![ROBE, MICHAEL].into_iter().collect());
+    assert_eq!(parse_query("pizza", MICHAEL).unwrap().users, all_user_ids().into_iter().collect());
+    assert!(parse_query("from:nobody", MICHAEL).is_err());
+}
+
+#[test]
+fn before_and_after_take_days_months_and_years() {
+    let query = parse_query("after:2016-03 before:2017", ROBE).unwrap();
+    assert_eq!(query.after, NaiveDate::from_ymd_opt(2016, 3, 31));
+    assert_eq!(query.before, NaiveDate::from_ymd_opt(2017, 1, 1));
+    let query = parse_query("after:2016-03-12", ROBE).unwrap();
+    assert_eq!(query.after, NaiveDate::from_ymd_opt(2016, 3, 12));
+    assert!(parse_query("after:yesterday", ROBE).is_err());
+}
+
+
+#[test]
+fn sort_picks_the_order() {
+    assert_eq!(parse_query("pizza sort:new", ROBE).unwrap().sort, SortOrder::Newest);
+    assert_eq!(parse_query("pizza sort:old", ROBE).unwrap().sort, SortOrder::Oldest);
+    assert_eq!(parse_query("pizza", ROBE).unwrap().sort, SortOrder::Relevance);
+    assert!(parse_query("pizza sort:loudest", ROBE).is_err());
+}
+
+#[test]
+fn quoted_words_are_exact_and_tilde_words_fuzzy() {
+    let query = parse_query("\"cats\" dogs ~restaraunt", ROBE).unwrap();
+    assert!(query.exact.contains("cats") && !query.exact.contains("dogs"));
+    assert!(query.fuzzy.contains("restaraunt") && !query.fuzzy.contains("dogs"));
+    assert_eq!(query.required, vec![vec![phrase("cats")], vec![phrase("dogs")], vec![phrase("restaraunt")]]);
+}
+
+#[test]
+fn nothing_to_search_for_is_an_error() {
+    assert!(parse_query("", ROBE).is_err());
+    assert!(parse_query("sort:new", ROBE).is_err());
+}
+
+#[test]
+fn ranking_favours_repeated_and_rarer_words() {
+    let root = scratch_root("ranking");
+    let log = group_log(&root, "2016-03-12");
+    for text in &["pizza and here", "pizza pizza here", "cheese and here", "pizza or there"] {
+        append(&log, &LogEntry::new(ROBE, text));
+    }
+    let index = SearchIndex::open(&root, &vec![log]).unwrap();
+
+    assert_eq!(find(&index, "pizza", ROBE)[0], 1);
+    // cheese is in one message, pizza in three
+    assert_eq!(find(&index, "pizza OR cheese", ROBE)[0], 2);
+    let config = SearchConfig::default();
+    assert!(index.relevance(2, &phrase("cheese"), &config) > index.relevance(0, &phrase("pizza"), &config));
+}
+
+#[test]
+fn highlighting_bolds_whole_words_only() {
+    let terms = vec![String::from("cat"), String::from("cats")];
+    let mut html = Html::new();
+    html.highlighted("Cats like the cat, not category <cat>", &terms);
+    assert_eq!(html.to_string(), "<b>Cats</b> like the <b>cat</b>, not category &lt;<b>cat</b>&gt;");
+}
+
+#[test]
+fn messages_are_read_back_together() {
+    let index = sample_index("messages");
+    let entries = index.messages(&[6, 2, 0, 2]).unwrap();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[&0].text, "deep dish pizza is the best pizza");
+    assert_eq!(entries[&2].text, "the dish was deep");
+    assert_eq!(entries[&6].text, "pizza tonight");
+
+    let result = index.result_with_context(1, 1).unwrap();
+    assert_eq!(result.before, vec![(ROBE, String::from("deep dish pizza is the best pizza"))]);
+    assert_eq!(result.after, vec![(ROBE, String::from("the dish was deep"))]);
+}
+
+#[test]
+fn phrases_only_match_in_order() {
+    let index = sample_index("phrases");
+    assert_eq!(find_any_order(&index, "\"deep dish\""), vec![0]);
+    assert_eq!(find_any_order(&index, "deep dish"), vec![0, 2]);
+}
+
+#[test]
+fn excluded_words_and_phrases_drop_messages() {
+    let index = sample_index("negation");
+    assert_eq!(find_any_order(&index, "pizza -pineapple"), vec![0, 6]);
+    assert_eq!(find_any_order(&index, "from:robe -\"deep dish\""), vec![2, 5, 6]);
+}
+
+#[test]
+fn either_side_of_or_matches() {
+    let index = sample_index("or");
+    assert_eq!(find_any_order(&index, "pineapple OR tonight"), vec![1, 6]);
+    assert_eq!(find_any_order(&index, "pizza pineapple OR tonight"), vec![1, 6]);
+}
+
+#[test]
+fn from_narrows_to_people() {
+    let index = sample_index("from");
+    assert_eq!(find(&index, "pizza from:me", MICHAEL), vec![1]);
+    assert_eq!(find_any_order(&index, "from:mikel"), vec![3]);
+}
+
+#[test]
+fn dates_narrow_to_the_days_between() {
+    let index = sample_index("dates");
+    assert_eq!(find_any_order(&index, "pizza after:2016"), vec![6]);
+    assert_eq!(find_any_order(&index, "pizza before:2017"), vec![0, 1]);
+    assert_eq!(find_any_order(&index, "pizza after:2016-03-11 before:2016-03-13"), vec![0, 1]);
+}
+
+#[test]
+fn sort_orders_by_date() {
+    let index = sample_index("sort");
+    assert_eq!(find(&index, "pizza sort:old", ROBE), vec![0, 1, 6]);
+    assert_eq!(find(&index, "pizza sort:new", ROBE), vec![6, 1, 0]);
+}
+
+#[test]
+fn long_replies_split_into_parts_telegram_takes() {
+    let long_word = "<&>\"".repeat(3000);
+    let mut html = Html::new();
+    html.bold(&long_word).line().code(&"pizza ".repeat(1000)).line().italic(&long_word).text(&long_word);
+
+    let parts = html.split(MAX_MESSAGE_LENGTH);
+    assert!(parts.len() > 1);
+    for part in &parts {
+        assert!(part.chars().count() <= MAX_MESSAGE_LENGTH, "part of {} characters", part.chars().count());
+        for tag in &["b", "i", "code"] {
+            assert_eq!(part.matches(&format!("<{}>", tag)).count(), part.matches(&format!("</{}>", tag)).count());
+        }
+    }
+    assert_eq!(parts.concat().replace('\n', ""), html.to_string().replace('\n', ""));
+}
+
+#[test]
+fn a_part_can_start_indented() {
+    let mut html = Html::new();
+    html.text(&"word ".repeat(100)).line().text("  indented");
+    assert_eq!(html.split(510), vec!["word ".repeat(100), String::from("  indented")]);
+}
+
+#[test]
+fn words_find_their_other_forms_unless_quoted() {
+    let index = sample_index("stemming");
+    assert_eq!(find_any_order(&index, "cats"), vec![3, 4]);
+    assert_eq!(find_any_order(&index, "\"cats\""), vec![3]);
+}
+
+#[test]
+fn tilde_words_match_typos() {
+    let index = sample_index("fuzzy");
+    assert_eq!(find_any_order(&index, "~restaraunt"), vec![5]);
+    assert_eq!(find_any_order(&index, "restaraunt"), Vec::<DocId>::new());
+}
+
+#[test]
+fn counts_go_by_person_and_month() {
+    let index = sample_index("count");
+    let config = SearchConfig::default();
+    let mut query = parse_query("pizza", ROBE).unwrap();
+    index.expand(&mut query, &config);
+    let docs = index.search(&query);
+
+    // the first message says it twice
+    assert_eq!(count_by_user(&index, &docs, &query), vec![(ROBE, 3, 2), (MICHAEL, 1, 1)]);
+
+    let months = count_by_month(&index, &docs, &query);
+    assert_eq!(months.len(), 11);
+    assert_eq!(months[0], ((2016, 3), 3));
+    assert!(months[1..10].iter().all(|&(_, n)| n == 0));
+    assert_eq!(months[10], ((2017, 1), 1));
+}
+
+#[test]
+fn sparklines_scale_to_the_biggest() {
+    assert_eq!(sparkline(&[0, 1, 4, 8]), "▁▃▅█");
+    assert_eq!(sparkline(&[]), "");
+}
+
+#[test]
+fn grep_finds_the_newest_matches_first() {
+    assert!(compile_pattern("(").is_err());
+    let regex = compile_pattern("PIZ+A").unwrap();
+
+    let index = sample_index("grep");
+    let users = all_user_ids().into_iter().collect();
+    let outcome = grep(&index, &regex, &users, GROUP, &GrepLimits::default()).unwrap();
+    let texts : Vec<&str> = outcome.matches.iter().map(|m| m.text.as_str()).collect();
+    assert_eq!(texts, vec!["pizza tonight", "pizza with pineapple", "deep dish pizza is the best pizza"]);
+    assert_eq!(outcome.matches[2].spans, vec![(10, 15), (28, 33)]);
+    assert!(outcome.cut_short.is_none());
+
+    let limits = GrepLimits { max_matches: 1, ..GrepLimits::default() };
+    assert_eq!(grep(&index, &regex, &users, GROUP, &limits).unwrap().matches.len(), 1);
+}
+
+#[test]
+fn grep_from_is_a_filter_only_when_it_names_someone() {
+    let (pattern, users) = parse_grep("pi+zza from:robe from:me", MICHAEL).unwrap();
+    assert_eq!(pattern, "pi+zza");
+    assert_eq!(users, vec![ROBE, MICHAEL].into_iter().collect());
+
+    let (pattern, users) = parse_grep("from:the.*start", MICHAEL).unwrap();
+    assert_eq!(pattern, "from:the.*start");
+    assert_eq!(users, all_user_ids().into_iter().collect());
+    assert_eq!(parse_grep("pizza from:x+", MICHAEL).unwrap().0, "pizza from:x+");
+
+    // ahead of a pattern it can only have meant someone
+    assert!(parse_grep("from:nobody pizza", MICHAEL).is_err());
+    assert!(parse_grep("from:robe", MICHAEL).is_err());
+}
+
+#[test]
+fn messages_are_dated_by_when_they_were_sent() {
+    let root = scratch_root("history");
+    let history = root.join("history.log");
+    let sent = |user_id:u64, text:&str, date:NaiveDate| {
+        let mut entry = LogEntry::new(user_id, text);
+        entry.timestamp = Some(date.and_hms(12, 0, 0).timestamp());
+        entry
+    };
+    append(&history, &sent(ROBE, "pizza in the old days", NaiveDate::from_ymd(2015, 6, 1)));
+    append(&history, &sent(MICHAEL, "more pizza", NaiveDate::from_ymd(2015, 8, 20)));
+    append(&history, &LogEntry::new(ROBE, "pizza some time"));
+    let log = group_log(&root, "2016-03-12");
+    append(&log, &LogEntry::new(ROBE, "pizza now"));
+    let logs = vec![history, log];
+
+    let check = |index:&SearchIndex| {
+        assert_eq!(index.date(0), NaiveDate::from_ymd_opt(2015, 6, 1));
+        assert_eq!(index.date(2), None);
+        assert_eq!(index.date(3), NaiveDate::from_ymd_opt(2016, 3, 12));
+        assert_eq!(find_any_order(index, "pizza before:2016"), vec![0, 1]);
+        assert_eq!(find_any_order(index, "pizza after:2015-07"), vec![1, 3]);
+        assert_eq!(find(index, "pizza sort:old", ROBE), vec![2, 0, 1, 3]);
+
+        let months = count_by_month(index, &[0, 1, 2, 3], &parse_query("pizza", ROBE).unwrap());
+        assert_eq!(months.len(), 10);
+        assert_eq!(months[0], ((2015, 6), 1));
+        assert_eq!(months[2], ((2015, 8), 1));
+        assert_eq!(months[9], ((2016, 3), 1));
+    };
+    check(&SearchIndex::open(&root, &logs).unwrap());
+    // and again from the saved index
+    check(&SearchIndex::open(&root, &logs).unwrap());
+}